@@ -14,7 +14,7 @@ pub mod linked_list;
 use fixed_size_block::FixedSizeBlockAllocator;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 512 * 1024; // 512 KiB
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> =
@@ -55,6 +55,28 @@ pub fn init_heap(
 }
 
 
+/// Heap usage, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Total size of the heap.
+    pub size: usize,
+    /// Bytes in live allocations.
+    pub allocated: usize,
+    /// Bytes taken from the heap, including freed blocks kept for reuse.
+    pub reserved: usize,
+}
+
+/// Returns the current heap usage.
+pub fn stats() -> HeapStats {
+    let allocator = ALLOCATOR.lock();
+    HeapStats {
+        size: allocator.size(),
+        allocated: allocator.allocated(),
+        reserved: allocator.reserved(),
+    }
+}
+
+
 /// Align the given address `addr` upwards to alignment `align`.
 /// See here for explanation:
 /// https://os.phil-opp.com/allocator-designs/#address-alignment
@@ -2,6 +2,7 @@ use alloc::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr};
 
 use super::Locked;
+use crate::trace;
 
 /// The block sizes to use.
 ///
@@ -21,6 +22,8 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    /// Bytes in live allocations, counting whole blocks for small ones.
+    allocated: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -29,6 +32,7 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [None; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            allocated: 0,
         }
     }
 
@@ -41,6 +45,22 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    /// Returns the size of the heap in bytes.
+    pub fn size(&self) -> usize {
+        self.fallback_allocator.size()
+    }
+
+    /// Returns the number of bytes in live allocations.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the number of bytes taken from the heap, including free
+    /// blocks kept in the block lists for reuse.
+    pub fn reserved(&self) -> usize {
+        self.fallback_allocator.used()
+    }
+
     /// Allocates using the fallback allocator.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
@@ -54,7 +74,7 @@ impl FixedSizeBlockAllocator {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -73,11 +93,18 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.allocated += allocated_size(&layout);
         }
+        trace!("alloc {} bytes (align {}) at {:p}", layout.size(), layout.align(), ptr);
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        trace!("dealloc {} bytes at {:p}", layout.size(), ptr);
         let mut allocator = self.lock();
+        allocator.allocated -= allocated_size(&layout);
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
@@ -99,6 +126,12 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
 }
 
 
+/// Returns the number of bytes an allocation with the given layout takes
+/// up: the whole block for small allocations, otherwise its size.
+fn allocated_size(layout: &Layout) -> usize {
+    list_index(layout).map_or(layout.size(), |index| BLOCK_SIZES[index])
+}
+
 /// Choose an appropriate block size for the given layout.
 ///
 /// Returns an index into the `BLOCK_SIZES` array.
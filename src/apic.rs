@@ -0,0 +1,329 @@
+use core::arch::x86_64::__cpuid;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicU64, AtomicU8, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::model_specific::Msr,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Size4KiB,
+    },
+    PhysAddr,
+};
+
+use crate::interrupts::InterruptIndex;
+use crate::memory;
+use crate::pit;
+use crate::time::tsc;
+
+/// Model-specific register holding the local APIC's physical base address.
+const IA32_APIC_BASE: u32 = 0x1b;
+/// Model-specific register holding the TSC value at which the timer fires
+/// when running in TSC-deadline mode.
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+/// Global enable flag in `IA32_APIC_BASE`.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+// Offsets of the local APIC registers from the base address.
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xb0;
+/// The first of the 8 in-service registers, which hold one bit per vector.
+const REG_ISR: usize = 0x100;
+const REG_SPURIOUS: usize = 0xf0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3e0;
+
+/// Software enable flag in the spurious interrupt vector register.
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+/// Mask flag in the local vector table entries.
+const LVT_MASKED: u32 = 1 << 16;
+/// Delivery mode of an interrupt command that sends an NMI.
+const ICR_NMI: u32 = 0b100 << 8;
+/// Level flag of an interrupt command; always set except for INIT de-assert.
+const ICR_ASSERT: u32 = 1 << 14;
+/// Destination shorthand of an interrupt command for all CPUs except the
+/// one sending it.
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+/// Divide configuration for a divisor of 16.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+/// How long to count timer ticks against the PIT during calibration.
+const CALIBRATION_MS: u32 = 10;
+
+/// Virtual address the local APIC registers are mapped at, or 0 if the
+/// APIC has not been initialized yet.
+static APIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Number of APIC timer ticks (after the divider) per millisecond.
+static TIMER_TICKS_PER_MS: AtomicU64 = AtomicU64::new(0);
+/// The mode the timer was last armed in, as a `TimerMode`.
+static TIMER_MODE: AtomicU8 = AtomicU8::new(TimerMode::OneShot as u8);
+/// Number of APIC timer interrupts received so far.
+static TIMER_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// Function to call whenever the APIC timer fires.
+static TIMER_CALLBACK: Mutex<Option<fn()>> = Mutex::new(None);
+
+/// Errors that can occur while setting up the local APIC.
+#[derive(Debug)]
+pub enum ApicError {
+    /// The CPU does not have a local APIC.
+    NotPresent,
+    /// The APIC registers could not be mapped into virtual memory.
+    MapFailed(MapToError<Size4KiB>),
+}
+
+/// The operating modes of the local APIC timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimerMode {
+    /// Count down once from the initial count, then stop.
+    OneShot = 0b00,
+    /// Count down repeatedly, reloading the initial count each time.
+    Periodic = 0b01,
+    /// Fire once the TSC reaches the value in `IA32_TSC_DEADLINE`.
+    TscDeadline = 0b10,
+}
+
+impl TimerMode {
+    /// The bits to set in the LVT timer register to select this mode.
+    fn lvt_bits(self) -> u32 {
+        (self as u32) << 17
+    }
+}
+
+
+/// Initialize the local APIC of the current CPU and calibrate its timer
+/// against the PIT. The timer is left masked until one of the `start_*`
+/// or `arm_*` functions is called.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), ApicError> {
+    // CPUID.01h:EDX[9] indicates an on-chip APIC
+    if unsafe { __cpuid(0x1) }.edx & (1 << 9) == 0 {
+        return Err(ApicError::NotPresent);
+    }
+
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    let base_value = unsafe { base_msr.read() };
+    let phys = PhysAddr::new(base_value & 0x000f_ffff_ffff_f000);
+    let virt = unsafe { memory::map_mmio(phys, 4096, mapper, frame_allocator) }
+        .map_err(ApicError::MapFailed)?;
+    APIC_BASE.store(virt.as_u64(), Ordering::SeqCst);
+
+    unsafe {
+        base_msr.write(base_value | APIC_BASE_ENABLE);
+        write(REG_SPURIOUS,
+            SPURIOUS_ENABLE | InterruptIndex::ApicSpurious.as_u8() as u32);
+    }
+
+    calibrate_timer();
+    Ok(())
+}
+
+/// Returns the ID of the local APIC of the current CPU.
+pub fn id() -> u8 {
+    unsafe { (read(REG_ID) >> 24) as u8 }
+}
+
+/// Signal the end of an interrupt to the local APIC. Must be called at the
+/// end of every handler for an interrupt delivered through the APIC.
+pub fn end_of_interrupt() {
+    if APIC_BASE.load(Ordering::Relaxed) != 0 {
+        unsafe { write(REG_EOI, 0) };
+    }
+}
+
+/// Returns whether the local APIC delivered `vector` and is waiting for
+/// its EOI. Always false if the APIC has not been initialized.
+pub fn is_in_service(vector: u8) -> bool {
+    if APIC_BASE.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+    let isr = unsafe { read(REG_ISR + 0x10 * (vector as usize / 32)) };
+    isr & (1 << (vector % 32)) != 0
+}
+
+/// Send an NMI to all other CPUs, whose NMI handler halts them once the
+/// kernel is panicking. Does nothing if the APIC has not been initialized.
+pub fn halt_other_cpus() {
+    if APIC_BASE.load(Ordering::Relaxed) != 0 {
+        unsafe {
+            write(REG_ICR_HIGH, 0);
+            write(REG_ICR_LOW, ICR_NMI | ICR_ASSERT | ICR_ALL_EXCLUDING_SELF);
+        }
+    }
+}
+
+/// Read a 32-bit local APIC register.
+///
+/// This function is unsafe because the APIC must have been mapped by
+/// `init` and `offset` must be a valid register offset.
+unsafe fn read(offset: usize) -> u32 {
+    let base = APIC_BASE.load(Ordering::Relaxed) as usize;
+    ptr::read_volatile((base + offset) as *const u32)
+}
+
+/// Write a 32-bit local APIC register.
+///
+/// This function is unsafe because the APIC must have been mapped by
+/// `init` and `offset` must be a valid register offset.
+unsafe fn write(offset: usize, value: u32) {
+    let base = APIC_BASE.load(Ordering::Relaxed) as usize;
+    ptr::write_volatile((base + offset) as *mut u32, value);
+}
+
+
+// Timer -----------------------------------------------------------------------
+
+/// Measure how fast the APIC timer ticks by letting it run for
+/// `CALIBRATION_MS` milliseconds of PIT time.
+fn calibrate_timer() {
+    interrupts::without_interrupts(|| unsafe {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        write(REG_LVT_TIMER, LVT_MASKED | TimerMode::OneShot.lvt_bits()
+            | InterruptIndex::ApicTimer.as_u8() as u32);
+
+        write(REG_TIMER_INITIAL, u32::MAX);
+        pit::wait_ms(CALIBRATION_MS);
+        let remaining = read(REG_TIMER_CURRENT);
+        write(REG_TIMER_INITIAL, 0);
+
+        let elapsed = (u32::MAX - remaining) as u64;
+        TIMER_TICKS_PER_MS.store(elapsed / CALIBRATION_MS as u64, Ordering::SeqCst);
+    });
+}
+
+/// Returns the calibrated number of APIC timer ticks per millisecond.
+pub fn timer_ticks_per_ms() -> u64 {
+    TIMER_TICKS_PER_MS.load(Ordering::Relaxed)
+}
+
+/// Returns whether the CPU supports TSC-deadline mode for the APIC timer.
+pub fn supports_tsc_deadline() -> bool {
+    // CPUID.01h:ECX[24]
+    unsafe { __cpuid(0x1) }.ecx & (1 << 24) != 0
+}
+
+/// Returns the mode the timer was last armed in.
+pub fn timer_mode() -> TimerMode {
+    match TIMER_MODE.load(Ordering::Relaxed) {
+        0b01 => TimerMode::Periodic,
+        0b10 => TimerMode::TscDeadline,
+        _ => TimerMode::OneShot,
+    }
+}
+
+/// Returns how many times the APIC timer has fired.
+pub fn timer_interrupts() -> u64 {
+    TIMER_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Set a function to be called (in interrupt context) every time the APIC
+/// timer fires. Pass `None` to remove it.
+pub fn set_timer_callback(callback: Option<fn()>) {
+    interrupts::without_interrupts(|| {
+        *TIMER_CALLBACK.lock() = callback;
+    });
+}
+
+/// Start the timer in periodic mode, firing every `period_ns` nanoseconds.
+pub fn start_periodic(period_ns: u64) {
+    set_lvt_timer(TimerMode::Periodic);
+    unsafe { write(REG_TIMER_INITIAL, ns_to_timer_ticks(period_ns)) };
+}
+
+/// Arm the timer to fire once, `ns` nanoseconds from now. Uses TSC-deadline
+/// mode when the CPU supports it, and the one-shot countdown otherwise.
+pub fn arm_oneshot_ns(ns: u64) {
+    if supports_tsc_deadline() {
+        let ticks = tsc::ns_to_ticks(ns).max(1);
+        arm_tsc_deadline(tsc::read().saturating_add(ticks));
+    } else {
+        set_lvt_timer(TimerMode::OneShot);
+        unsafe { write(REG_TIMER_INITIAL, ns_to_timer_ticks(ns)) };
+    }
+}
+
+/// Arm the timer to fire once the TSC reaches `deadline`. The CPU must
+/// support TSC-deadline mode (see `supports_tsc_deadline`).
+pub fn arm_tsc_deadline(deadline: u64) {
+    set_lvt_timer(TimerMode::TscDeadline);
+    unsafe {
+        // the switch to TSC-deadline mode must be visible before the MSR
+        // write, see Intel SDM Vol. 3A, 10.5.4.1 (compiles to `mfence`)
+        fence(Ordering::SeqCst);
+        Msr::new(IA32_TSC_DEADLINE).write(deadline);
+    }
+}
+
+/// Stop the timer and mask its interrupt.
+pub fn stop_timer() {
+    unsafe {
+        write(REG_LVT_TIMER, LVT_MASKED);
+        write(REG_TIMER_INITIAL, 0);
+        if supports_tsc_deadline() {
+            Msr::new(IA32_TSC_DEADLINE).write(0);
+        }
+    }
+}
+
+/// Program the LVT timer entry for the given mode, unmasked and delivering
+/// to `InterruptIndex::ApicTimer`.
+fn set_lvt_timer(mode: TimerMode) {
+    TIMER_MODE.store(mode as u8, Ordering::Relaxed);
+    unsafe {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        write(REG_LVT_TIMER,
+            mode.lvt_bits() | InterruptIndex::ApicTimer.as_u8() as u32);
+    }
+}
+
+/// Convert a duration in nanoseconds to an initial count for the timer,
+/// clamped to the range of the 32-bit counter.
+fn ns_to_timer_ticks(ns: u64) -> u32 {
+    let ticks = ns.saturating_mul(TIMER_TICKS_PER_MS.load(Ordering::Relaxed))
+        / 1_000_000;
+    ticks.max(1).min(u32::MAX as u64) as u32
+}
+
+/// Called by the interrupt handler for `InterruptIndex::ApicTimer`.
+pub(crate) fn handle_timer_interrupt() {
+    TIMER_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    if let Some(callback) = *TIMER_CALLBACK.lock() {
+        callback();
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_timer_lvt() {
+    let vector = InterruptIndex::ApicTimer.as_u8() as u32;
+    // `init` leaves the timer masked, but already pointing at its vector
+    let lvt = unsafe { read(REG_LVT_TIMER) };
+    assert_eq!(lvt & 0xff, vector);
+    assert_ne!(lvt & LVT_MASKED, 0);
+
+    let before = timer_interrupts();
+    start_periodic(1_000_000);
+    let lvt = unsafe { read(REG_LVT_TIMER) };
+    assert_eq!(lvt & 0xff, vector);
+    assert_eq!(lvt & LVT_MASKED, 0);
+    assert_eq!(lvt & TimerMode::TscDeadline.lvt_bits(), 0);
+    assert_ne!(lvt & TimerMode::Periodic.lvt_bits(), 0);
+    assert_eq!(timer_mode(), TimerMode::Periodic);
+
+    pit::wait_ms(10);
+    stop_timer();
+    assert!(timer_interrupts() > before);
+    assert_ne!(unsafe { read(REG_LVT_TIMER) } & LVT_MASKED, 0);
+    // every timer interrupt got its EOI
+    assert!(!is_in_service(vector as u8));
+}
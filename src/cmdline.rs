@@ -0,0 +1,46 @@
+/// The kernel command line, as whitespace-separated `key=value` options
+/// and bare flags.
+///
+/// The bootloader we use cannot pass a command line to the kernel, so it
+/// is baked in at build time from the `KERNEL_CMDLINE` environment
+/// variable, e.g. `KERNEL_CMDLINE="keymap=de" cargo run`.
+pub fn get() -> &'static str {
+    option_env!("KERNEL_CMDLINE").unwrap_or("")
+}
+
+/// Returns the value of the option `key=value` on the command line, if it
+/// is present. If an option is repeated, the last value wins.
+pub fn value(key: &str) -> Option<&'static str> {
+    find_value(get(), key)
+}
+
+/// Returns whether the bare flag `key` is present on the command line.
+pub fn flag(key: &str) -> bool {
+    get().split_whitespace().any(|option| option == key)
+}
+
+/// Finds the value of the option `key=value` in `cmdline`.
+fn find_value<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline.split_whitespace()
+        .filter_map(|option| {
+            let mut parts = option.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if k == key => Some(v),
+                _ => None,
+            }
+        })
+        .last()
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_find_value() {
+    let cmdline = "keymap=de  quiet scancodes=2 keymap=us";
+    assert_eq!(find_value(cmdline, "scancodes"), Some("2"));
+    assert_eq!(find_value(cmdline, "keymap"), Some("us"));
+    assert_eq!(find_value(cmdline, "quiet"), None);
+    assert_eq!(find_value(cmdline, "missing"), None);
+    assert_eq!(find_value("", "keymap"), None);
+}
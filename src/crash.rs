@@ -0,0 +1,316 @@
+use core::fmt::{self, Write};
+use core::ops::Range;
+use core::panic::PanicInfo;
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::control::{Cr0, Cr2, Cr3},
+    structures::idt::InterruptStackFrame,
+};
+
+mod backtrace;
+mod screen;
+
+use screen::Screen;
+
+use crate::apic;
+use crate::emergency;
+use crate::hlt_loop;
+use crate::interrupts::exception_name;
+use crate::log::ring;
+use crate::serial::{self, Output};
+use crate::vga_buffer::Color;
+
+/// Most return addresses shown in the backtrace.
+const MAX_FRAMES: usize = 8;
+/// Return addresses shown per row of the backtrace.
+const FRAMES_PER_ROW: usize = 4;
+/// Columns left blank at the left edge of the screen.
+const MARGIN: usize = 2;
+
+const BACKGROUND: Color = Color::Blue;
+const TEXT: Color = Color::White;
+const HEADING: Color = Color::Yellow;
+
+// Rows of the parts of the crash screen. Recent log messages fill the rows
+// from `LOG_ROW` to the bottom bar.
+const LOCATION_ROW: usize = 2;
+const MESSAGE_ROWS: Range<usize> = 3..9;
+const REGISTER_ROWS: Range<usize> = 10..15;
+const BACKTRACE_ROWS: Range<usize> = 16..19;
+const LOG_ROW: usize = 20;
+
+/// The state of the CPU saved when an exception occurred.
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionInfo {
+    pub vector: u8,
+    pub error_code: Option<u64>,
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64,
+}
+
+/// The exception being handled, if any, recorded by its handler.
+static EXCEPTION: Mutex<Option<ExceptionInfo>> = Mutex::new(None);
+
+/// Record that exception `vector` is being handled, so that the crash
+/// screen shows its registers if the handler panics.
+pub fn record_exception(vector: u8, error_code: Option<u64>, stack_frame: &InterruptStackFrame) {
+    *emergency::lock(&EXCEPTION) = Some(ExceptionInfo {
+        vector,
+        error_code,
+        instruction_pointer: stack_frame.instruction_pointer.as_u64(),
+        code_segment: stack_frame.code_segment,
+        cpu_flags: stack_frame.cpu_flags,
+        stack_pointer: stack_frame.stack_pointer.as_u64(),
+        stack_segment: stack_frame.stack_segment,
+    });
+}
+
+/// Show a crash screen for the panic described by `info` and halt all
+/// CPUs. The screen shows the panic message and location, the registers
+/// of the exception being handled, a backtrace and the most recent log
+/// messages; the same report is written to the serial port of the kernel
+/// log.
+pub fn report(info: &PanicInfo) -> ! {
+    interrupts::disable();
+    if emergency::enter() {
+        // panicked while reporting a crash; leave the screen as it is
+        hlt_loop();
+    }
+    apic::halt_other_cpus();
+    show(info);
+    hlt_loop();
+}
+
+/// Draw the crash screen for `info` and write the report to the serial
+/// port of the kernel log, like `report`, but return afterwards. For
+/// tests checking the screen; everything else should call `report`.
+pub fn show(info: &PanicInfo) {
+    let mut screen = Screen::open(TEXT, BACKGROUND);
+    let rows = screen.rows();
+    draw_bar(&mut screen, 0, "KERNEL PANIC");
+    serial::print_to(Output::Log, format_args!("\n*** KERNEL PANIC ***\n"));
+
+    let mut section = Section::new(&mut screen, LOCATION_ROW..LOCATION_ROW + 1);
+    let _ = match info.location() {
+        Some(location) => writeln!(section, "Panicked at {}:{}:{}",
+            location.file(), location.line(), location.column()),
+        None => writeln!(section, "Panicked at an unknown location"),
+    };
+
+    let mut section = Section::new(&mut screen, MESSAGE_ROWS);
+    let _ = match info.message() {
+        Some(message) => writeln!(section, "{}", message),
+        None => writeln!(section, "(no message)"),
+    };
+
+    let mut section = Section::new(&mut screen, REGISTER_ROWS);
+    let _ = write_registers(&mut section);
+
+    let mut section = Section::new(&mut screen, BACKTRACE_ROWS);
+    section.heading("Backtrace");
+    let mut frames = 0;
+    backtrace::walk(MAX_FRAMES, |address| {
+        frames += 1;
+        let separator = if frames % FRAMES_PER_ROW == 0 { "\n" } else { "  " };
+        let _ = write!(section, "{:#018x}{}", address, separator);
+    });
+    if frames == 0 {
+        let _ = writeln!(section, "(none)");
+    } else if frames % FRAMES_PER_ROW != 0 {
+        let _ = writeln!(section);
+    }
+
+    // one message per row, cut off at the edge of the screen
+    let log_rows = LOG_ROW..rows.saturating_sub(1).max(LOG_ROW);
+    let mut section = Section::new(&mut screen, log_rows.clone());
+    section.wrap = false;
+    section.heading("Recent messages");
+    ring::for_each_entry(ring::tail_seq(log_rows.len().saturating_sub(1)), |entry| {
+        let _ = writeln!(section, "{}", entry);
+    });
+
+    draw_bar(&mut screen, rows.saturating_sub(1), "System halted");
+    serial::print_to(Output::Log, format_args!("*** System halted ***\n"));
+}
+
+/// Write the registers of the exception being handled, if any, and the
+/// control registers.
+fn write_registers(section: &mut Section) -> fmt::Result {
+    let exception = *emergency::lock(&EXCEPTION);
+    match exception {
+        Some(exception) => {
+            section.heading("Exception");
+            write!(section, "{} (vector {})", exception_name(exception.vector), exception.vector)?;
+            if let Some(error_code) = exception.error_code {
+                write!(section, ", error code {:#x}", error_code)?;
+            }
+            writeln!(section)?;
+            writeln!(section, "RIP {:#018x}  CS {:#06x}  RFLAGS {:#010x}",
+                exception.instruction_pointer, exception.code_segment, exception.cpu_flags)?;
+            writeln!(section, "RSP {:#018x}  SS {:#06x}",
+                exception.stack_pointer, exception.stack_segment)?;
+        }
+        None => {
+            section.heading("Registers");
+            writeln!(section, "No exception was being handled.")?;
+        }
+    }
+    let (page_table, _) = Cr3::read();
+    writeln!(section, "CR0 {:#010x}  CR2 {:#018x}  CR3 {:#018x}",
+        Cr0::read().bits(), Cr2::read().as_u64(), page_table.start_address().as_u64())
+}
+
+/// Fill `row` with the inverted colors and show `title` in the middle of
+/// it.
+fn draw_bar(screen: &mut Screen, row: usize, title: &str) {
+    let start = screen.columns().saturating_sub(title.len()) / 2;
+    for col in 0..screen.columns() {
+        screen.put(row, col, ' ', BACKGROUND, TEXT);
+    }
+    for (col, c) in (start..screen.columns()).zip(title.chars()) {
+        screen.put(row, col, c, BACKGROUND, TEXT);
+    }
+}
+
+/// Writes text to a range of rows of the crash screen, and all of it to
+/// the serial port of the kernel log, including what does not fit.
+struct Section<'a> {
+    screen: &'a mut Screen,
+    row: usize,
+    col: usize,
+    bottom: usize,
+    /// Whether lines too long for the screen continue on the next row
+    /// rather than being cut off.
+    wrap: bool,
+    /// Whether text is also written to the serial port of the kernel log.
+    mirror: bool,
+    foreground: Color,
+}
+
+impl<'a> Section<'a> {
+    fn new(screen: &'a mut Screen, rows: Range<usize>) -> Section<'a> {
+        Section {
+            screen,
+            row: rows.start,
+            col: MARGIN,
+            bottom: rows.end,
+            wrap: true,
+            mirror: true,
+            foreground: TEXT,
+        }
+    }
+
+    /// Write `title` on a line of its own, in the heading color.
+    fn heading(&mut self, title: &str) {
+        self.foreground = HEADING;
+        let _ = writeln!(self, "{}:", title);
+        self.foreground = TEXT;
+    }
+
+    fn new_line(&mut self) {
+        self.row += 1;
+        self.col = MARGIN;
+    }
+}
+
+impl Write for Section<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.mirror {
+            serial::print_to(Output::Log, format_args!("{}", s));
+        }
+        for c in s.chars() {
+            if c == '\n' {
+                self.new_line();
+                continue;
+            }
+            if self.col >= self.screen.columns() {
+                if !self.wrap {
+                    continue;
+                }
+                self.new_line();
+            }
+            if self.row < self.bottom {
+                self.screen.put(self.row, self.col, c, self.foreground, BACKGROUND);
+            }
+            self.col += 1;
+        }
+        Ok(())
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+/// Returns the character and color byte of a cell of the VGA text buffer.
+#[cfg(test)]
+fn text_cell(row: usize, col: usize) -> (u8, u8) {
+    use crate::vga_buffer::BUFFER_WIDTH;
+
+    let cells = 0xb8000 as *const u16;
+    let cell = unsafe { core::ptr::read_volatile(cells.add(row * BUFFER_WIDTH + col)) };
+    (cell as u8, (cell >> 8) as u8)
+}
+
+#[test_case]
+fn test_section() {
+    use crate::vga_buffer;
+
+    let saved = vga_buffer::save_screen();
+    interrupts::without_interrupts(|| {
+        let mut screen = Screen::Text;
+        let columns = screen.columns();
+        for row in 5..9 {
+            for col in 0..columns {
+                screen.put(row, col, ' ', TEXT, BACKGROUND);
+            }
+        }
+
+        // long lines continue on the next row, and rows past the end of
+        // the section are left alone
+        let mut section = Section::new(&mut screen, 5..7);
+        section.mirror = false;
+        for _ in MARGIN..columns {
+            let _ = section.write_str("a");
+        }
+        let _ = write!(section, "b\nc");
+        assert_eq!(text_cell(5, MARGIN).0, b'a');
+        assert_eq!(text_cell(5, columns - 1).0, b'a');
+        assert_eq!(text_cell(6, MARGIN).0, b'b');
+        assert_eq!(text_cell(7, MARGIN).0, b' ');
+
+        // without wrapping, long lines are cut off at the edge
+        let mut section = Section::new(&mut screen, 7..9);
+        section.mirror = false;
+        section.wrap = false;
+        for _ in MARGIN..columns {
+            let _ = section.write_str("x");
+        }
+        let _ = writeln!(section, "y");
+        assert_eq!(text_cell(7, columns - 1).0, b'x');
+        assert_eq!(text_cell(8, MARGIN).0, b' ');
+    });
+    vga_buffer::restore_screen(&saved);
+}
+
+#[test_case]
+fn test_draw_bar() {
+    use crate::vga_buffer;
+
+    let saved = vga_buffer::save_screen();
+    interrupts::without_interrupts(|| {
+        let mut screen = Screen::Text;
+        let columns = screen.columns();
+        draw_bar(&mut screen, 5, "TITLE");
+        let inverted = (TEXT as u8) << 4 | BACKGROUND as u8;
+        assert_eq!(text_cell(5, 0), (b' ', inverted));
+        assert_eq!(text_cell(5, (columns - 5) / 2), (b'T', inverted));
+        assert_eq!(text_cell(5, (columns - 5) / 2 + 4), (b'E', inverted));
+        assert_eq!(text_cell(5, columns - 1), (b' ', inverted));
+    });
+    vga_buffer::restore_screen(&saved);
+}
@@ -0,0 +1,87 @@
+/// Largest distance between two saved frame pointers that is taken for a
+/// real stack frame rather than garbage.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+/// Call `f` with the return address of each function on the stack,
+/// innermost first, stopping after `max` of them.
+///
+/// Follows the chain of saved frame pointers, which relies on the kernel
+/// being built with frame pointers (`eliminate-frame-pointer` in the
+/// target specification). Every step is checked for plausibility, but a
+/// corrupted stack may still make this fault.
+pub(super) fn walk(max: usize, f: impl FnMut(u64)) {
+    let frame_pointer: u64;
+    unsafe { llvm_asm!("mov %rbp, $0" : "=r"(frame_pointer)) };
+    walk_from(frame_pointer, max, f);
+}
+
+/// Like `walk`, starting at the stack frame `frame_pointer` points to.
+fn walk_from(mut frame_pointer: u64, max: usize, mut f: impl FnMut(u64)) {
+    for _ in 0..max {
+        if frame_pointer == 0 || frame_pointer % 8 != 0 {
+            break;
+        }
+        // each frame starts with the caller's frame pointer, followed by
+        // the return address
+        let frame = frame_pointer as *const u64;
+        let (caller_frame, return_address) = unsafe { (*frame, *frame.add(1)) };
+        if return_address == 0 {
+            break;
+        }
+        f(return_address);
+
+        // the stack grows down, so callers' frames are at higher addresses
+        if caller_frame <= frame_pointer || caller_frame - frame_pointer > MAX_FRAME_SIZE {
+            break;
+        }
+        frame_pointer = caller_frame;
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_walk() {
+    // three frames, each holding the caller's frame pointer and a return
+    // address, with the outermost frame ending the chain
+    let mut stack = [0u64; 6];
+    let base = stack.as_ptr() as u64;
+    stack[0] = base + 16;
+    stack[1] = 0x1000;
+    stack[2] = base + 32;
+    stack[3] = 0x2000;
+    stack[4] = 0;
+    stack[5] = 0x3000;
+
+    let mut addresses = [0; 4];
+    let mut count = 0;
+    walk_from(base, addresses.len(), |address| {
+        addresses[count] = address;
+        count += 1;
+    });
+    assert_eq!(&addresses[..count], &[0x1000, 0x2000, 0x3000]);
+
+    // `max` limits the number of frames
+    let mut count = 0;
+    walk_from(base, 2, |_| count += 1);
+    assert_eq!(count, 2);
+
+    // a frame pointer going the wrong way ends the walk
+    stack[2] = base;
+    let mut count = 0;
+    walk_from(base, 4, |_| count += 1);
+    assert_eq!(count, 2);
+
+    // so do a misaligned frame pointer and a null return address
+    let mut count = 0;
+    walk_from(base + 4, 4, |_| count += 1);
+    stack[1] = 0;
+    walk_from(base, 4, |_| count += 1);
+    assert_eq!(count, 0);
+
+    // the real stack has at least the caller of this test
+    let mut count = 0;
+    walk(4, |_| count += 1);
+    assert!(count >= 1);
+}
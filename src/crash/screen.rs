@@ -0,0 +1,76 @@
+use core::ptr;
+
+use crate::framebuffer;
+use crate::vga_buffer::{self, cp437, Color, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// Address of the VGA text buffer.
+const VGA_TEXT: usize = 0xb8000;
+
+/// The screen the crash report is drawn on: the VGA text buffer, or the
+/// text console of the framebuffer in a graphics mode.
+///
+/// The VGA text buffer is written to directly rather than through a
+/// writer, since the panic may have left the virtual consoles halfway
+/// through a switch.
+pub(super) enum Screen {
+    Text,
+    Graphics { rows: usize, columns: usize },
+}
+
+impl Screen {
+    /// Take over the screen and fill it with `background`.
+    pub(super) fn open(foreground: Color, background: Color) -> Screen {
+        let graphics = framebuffer::with_console(|console| {
+            console.set_color(foreground, background);
+            console.clear();
+            (console.rows(), console.columns())
+        });
+        if let Some((rows, columns)) = graphics {
+            return Screen::Graphics { rows, columns };
+        }
+
+        vga_buffer::set_cursor_visible(false);
+        let mut screen = Screen::Text;
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                screen.put(row, col, ' ', foreground, background);
+            }
+        }
+        screen
+    }
+
+    pub(super) fn rows(&self) -> usize {
+        match *self {
+            Screen::Text => BUFFER_HEIGHT,
+            Screen::Graphics { rows, .. } => rows,
+        }
+    }
+
+    pub(super) fn columns(&self) -> usize {
+        match *self {
+            Screen::Text => BUFFER_WIDTH,
+            Screen::Graphics { columns, .. } => columns,
+        }
+    }
+
+    /// Show `c` in the given cell. Cells off the screen are ignored.
+    pub(super) fn put(&mut self, row: usize, col: usize, c: char, foreground: Color, background: Color) {
+        match *self {
+            Screen::Text => {
+                if row < BUFFER_HEIGHT && col < BUFFER_WIDTH {
+                    let color = (background as u16) << 4 | foreground as u16;
+                    let cell = color << 8 | cp437::encode_or_unknown(c) as u16;
+                    let cells = VGA_TEXT as *mut u16;
+                    unsafe { ptr::write_volatile(cells.add(row * BUFFER_WIDTH + col), cell) };
+                }
+            }
+            Screen::Graphics { .. } => {
+                let mut bytes = [0; 4];
+                framebuffer::with_console(|console| {
+                    console.set_color(foreground, background);
+                    console.write_at(row, col, c.encode_utf8(&mut bytes));
+                });
+            }
+        }
+    }
+}
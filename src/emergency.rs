@@ -0,0 +1,47 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::{Mutex, MutexGuard};
+
+/// Whether the kernel is panicking, so that output has to get out no
+/// matter who holds the locks.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Switch printing to the emergency path, which breaks the locks of the
+/// screen and the serial ports instead of waiting for them. Called at the
+/// start of a panic; there is no way back.
+///
+/// Returns whether the emergency path was already in use, i.e. whether
+/// this is a panic during a panic.
+pub fn enter() -> bool {
+    ACTIVE.swap(true, Ordering::SeqCst)
+}
+
+/// Returns whether the kernel is panicking.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Lock `mutex`. When panicking, a mutex that is already locked is
+/// unlocked by force rather than waited for: whoever holds it was
+/// interrupted by the panic and will never run again. The data it
+/// protects may be halfway through an update, so it must stay usable in
+/// any state it can be left in.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    if is_active() {
+        if let Some(guard) = mutex.try_lock() {
+            return guard;
+        }
+        unsafe { mutex.force_unlock() };
+    }
+    mutex.lock()
+}
+
+/// Like `lock`, but returns `None` rather than waiting if `mutex` is
+/// already locked, unless the kernel is panicking.
+pub fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<T>> {
+    if is_active() {
+        Some(lock(mutex))
+    } else {
+        mutex.try_lock()
+    }
+}
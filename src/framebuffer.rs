@@ -0,0 +1,240 @@
+use core::fmt;
+use core::ptr;
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB},
+};
+
+mod bga;
+pub mod console;
+pub mod font;
+
+pub use console::TextConsole;
+pub use font::Font;
+
+use crate::cmdline;
+use crate::emergency;
+use crate::memory;
+use crate::pci;
+
+/// The text console on the framebuffer, once a graphics mode is set.
+static CONSOLE: Mutex<Option<TextConsole>> = Mutex::new(None);
+
+/// Errors that can occur while switching to a graphics mode.
+#[derive(Debug)]
+pub enum FramebufferError {
+    /// There is no Bochs/QEMU standard VGA card (e.g. `qemu -vga std`).
+    NotPresent,
+    /// The resolution is not supported, or could not be parsed.
+    InvalidMode,
+    /// The framebuffer or the VGA font could not be mapped into virtual
+    /// memory.
+    MapFailed(MapToError<Size4KiB>),
+}
+
+/// Returns the 32-bit pixel value of a color.
+pub const fn rgb(red: u8, green: u8, blue: u8) -> u32 {
+    (red as u32) << 16 | (green as u32) << 8 | blue as u32
+}
+
+/// A linear framebuffer with 32 bits per pixel. Drawing outside of it is
+/// clipped.
+pub struct Framebuffer {
+    base: usize,
+    width: usize,
+    height: usize,
+    /// Pixels from the start of one row to the start of the next.
+    stride: usize,
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer of `width` by `height` pixels at `base`.
+    ///
+    /// This function is unsafe because the caller must guarantee that
+    /// `height` rows of `stride` pixels at `base` are mapped, writable and
+    /// not used for anything else.
+    pub unsafe fn new(base: *mut u32, width: usize, height: usize, stride: usize) -> Framebuffer {
+        Framebuffer { base: base as usize, width, height, stride }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn row_ptr(&self, y: usize) -> *mut u32 {
+        (self.base as *mut u32).wrapping_add(y * self.stride)
+    }
+
+    /// Set the pixel at (`x`, `y`).
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            unsafe { ptr::write_volatile(self.row_ptr(y).add(x), color) };
+        }
+    }
+
+    /// Fill a rectangle with `color`.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let columns = x.min(self.width)..(x + width).min(self.width);
+        for y in y.min(self.height)..(y + height).min(self.height) {
+            let row = self.row_ptr(y);
+            for x in columns.clone() {
+                unsafe { ptr::write_volatile(row.add(x), color) };
+            }
+        }
+    }
+
+    /// Copy an image of `width` pixels per row, given row by row, with its
+    /// top left corner at (`x`, `y`).
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[u32]) {
+        if width == 0 {
+            return;
+        }
+        for (i, line) in pixels.chunks(width).enumerate() {
+            if y + i >= self.height {
+                break;
+            }
+            let row = self.row_ptr(y + i);
+            for (j, &pixel) in line.iter().enumerate().take(self.width.saturating_sub(x)) {
+                unsafe { ptr::write_volatile(row.add(x + j), pixel) };
+            }
+        }
+    }
+
+    /// Move everything up by `lines` rows of pixels, filling the rows
+    /// uncovered at the bottom with `fill`.
+    pub fn scroll_up(&mut self, lines: usize, fill: u32) {
+        let lines = lines.min(self.height);
+        for y in 0..self.height - lines {
+            unsafe { ptr::copy(self.row_ptr(y + lines), self.row_ptr(y), self.width) };
+        }
+        self.fill_rect(0, self.height - lines, self.width, lines, fill);
+    }
+}
+
+
+/// Switch to a graphics mode if the kernel command line asks for one with
+/// the `video=` option, e.g. `video=1024x768`. Without the option the
+/// screen stays in text mode.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), FramebufferError> {
+    let mode = match cmdline::value("video") {
+        Some(mode) => mode,
+        None => return Ok(()),
+    };
+    let (width, height) = parse_mode(mode).ok_or(FramebufferError::InvalidMode)?;
+    enable(width, height, mapper, frame_allocator)
+}
+
+/// Parses a resolution written as e.g. "1024x768".
+fn parse_mode(mode: &str) -> Option<(usize, usize)> {
+    let mut parts = mode.splitn(2, 'x');
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    Some((width, height))
+}
+
+/// Switch to a graphics mode with the given resolution and show a text
+/// console on it, which `print!` writes to from then on. The width must
+/// be a multiple of 8.
+pub fn enable(
+    width: usize,
+    height: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), FramebufferError> {
+    if width == 0 || height == 0 || width > bga::MAX_WIDTH || height > bga::MAX_HEIGHT
+        || width % font::GLYPH_WIDTH != 0
+    {
+        return Err(FramebufferError::InvalidMode);
+    }
+    let device = pci::find_device(bga::VENDOR_ID, bga::DEVICE_ID)
+        .ok_or(FramebufferError::NotPresent)?;
+    if !bga::is_present() {
+        return Err(FramebufferError::NotPresent);
+    }
+    let phys = device.memory_bar(0).ok_or(FramebufferError::NotPresent)?;
+
+    // the font has to be read while still in text mode
+    let font = Font::from_vga(mapper, frame_allocator).map_err(FramebufferError::MapFailed)?;
+    let base = unsafe { memory::map_mmio(phys, width * height * 4, mapper, frame_allocator) }
+        .map_err(FramebufferError::MapFailed)?;
+
+    bga::set_mode(width, height);
+    let framebuffer = unsafe { Framebuffer::new(base.as_mut_ptr(), width, height, width) };
+    let mut console = TextConsole::new(framebuffer, font);
+    console.clear();
+    interrupts::without_interrupts(|| *CONSOLE.lock() = Some(console));
+    Ok(())
+}
+
+/// Returns whether a graphics mode is set.
+pub fn is_enabled() -> bool {
+    interrupts::without_interrupts(|| emergency::lock(&CONSOLE).is_some())
+}
+
+/// Run `f` with the text console, if a graphics mode is set.
+pub fn with_console<R>(f: impl FnOnce(&mut TextConsole) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| emergency::lock(&CONSOLE).as_mut().map(f))
+}
+
+/// Run `f` with the framebuffer, if a graphics mode is set.
+pub fn with_framebuffer<R>(f: impl FnOnce(&mut Framebuffer) -> R) -> Option<R> {
+    with_console(|console| f(console.framebuffer()))
+}
+
+/// Write to the text console, if a graphics mode is set. Returns whether
+/// it was.
+pub(crate) fn print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        emergency::lock(&CONSOLE).as_mut().map(|console| console.write_fmt(args).unwrap()).is_some()
+    })
+}
+
+/// Like `print`, but returns `None` instead of waiting if the text console
+/// is in use.
+pub(crate) fn try_print(args: fmt::Arguments) -> Option<bool> {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut console = CONSOLE.try_lock()?;
+        Some(console.as_mut().map(|console| console.write_fmt(args).unwrap()).is_some())
+    })
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_parse_mode() {
+    assert_eq!(parse_mode("1024x768"), Some((1024, 768)));
+    assert_eq!(parse_mode("1024"), None);
+    assert_eq!(parse_mode("wide"), None);
+}
+
+#[test_case]
+fn test_framebuffer_drawing() {
+    use alloc::vec;
+
+    let (width, height) = (16, 8);
+    let mut pixels = vec![0u32; width * height];
+    let mut framebuffer = unsafe { Framebuffer::new(pixels.as_mut_ptr(), width, height, width) };
+    framebuffer.blit(0, 0, 2, &[1, 2, 3, 4]);
+    // partly off the framebuffer
+    framebuffer.fill_rect(14, 6, 4, 4, 5);
+    framebuffer.scroll_up(1, 6);
+
+    assert_eq!(&pixels[0..2], &[3, 4]);
+    assert_eq!(pixels[5 * width + 14], 5);
+    assert_eq!(pixels[6 * width + 15], 5);
+    assert_eq!(pixels[7 * width], 6);
+}
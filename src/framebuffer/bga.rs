@@ -0,0 +1,71 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// PCI vendor and device ID of the Bochs/QEMU standard VGA card.
+pub(super) const VENDOR_ID: u16 = 0x1234;
+pub(super) const DEVICE_ID: u16 = 0x1111;
+
+// Bochs graphics adapter (BGA) registers.
+const REG_ID: u16 = 0;
+const REG_WIDTH: u16 = 1;
+const REG_HEIGHT: u16 = 2;
+const REG_BPP: u16 = 3;
+const REG_ENABLE: u16 = 4;
+
+/// Oldest BGA version that supports 32 bits per pixel.
+const MIN_VERSION: u16 = 0xb0c2;
+/// Newest BGA version we know of.
+const MAX_VERSION: u16 = 0xb0c5;
+
+// Flags of the enable register.
+const ENABLED: u16 = 1 << 0;
+const LINEAR_FRAMEBUFFER: u16 = 1 << 6;
+
+/// Largest resolution every supported BGA version can show.
+pub(super) const MAX_WIDTH: usize = 1600;
+pub(super) const MAX_HEIGHT: usize = 1200;
+
+/// The index and data ports of the BGA registers.
+struct Registers {
+    index: Port<u16>,
+    data: Port<u16>,
+}
+
+impl Registers {
+    fn read(&mut self, register: u16) -> u16 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u16) {
+        unsafe {
+            self.index.write(register);
+            self.data.write(value);
+        }
+    }
+}
+
+static REGISTERS: Mutex<Registers> = Mutex::new(Registers {
+    index: Port::new(0x1ce),
+    data: Port::new(0x1cf),
+});
+
+
+/// Returns whether a BGA supporting 32 bits per pixel is present.
+pub(super) fn is_present() -> bool {
+    let version = REGISTERS.lock().read(REG_ID);
+    (MIN_VERSION..=MAX_VERSION).contains(&version)
+}
+
+/// Switch to a graphics mode of the given resolution with 32 bits per
+/// pixel, using the linear framebuffer.
+pub(super) fn set_mode(width: usize, height: usize) {
+    let mut registers = REGISTERS.lock();
+    registers.write(REG_ENABLE, 0);
+    registers.write(REG_WIDTH, width as u16);
+    registers.write(REG_HEIGHT, height as u16);
+    registers.write(REG_BPP, 32);
+    registers.write(REG_ENABLE, ENABLED | LINEAR_FRAMEBUFFER);
+}
@@ -0,0 +1,149 @@
+use core::fmt;
+
+use super::font::{Font, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::{rgb, Framebuffer};
+use crate::vga_buffer::{cp437, Color};
+
+/// The RGB values of the 16 VGA text mode colors, in order of their value.
+const PALETTE: [u32; 16] = [
+    rgb(0x00, 0x00, 0x00), rgb(0x00, 0x00, 0xaa), rgb(0x00, 0xaa, 0x00), rgb(0x00, 0xaa, 0xaa),
+    rgb(0xaa, 0x00, 0x00), rgb(0xaa, 0x00, 0xaa), rgb(0xaa, 0x55, 0x00), rgb(0xaa, 0xaa, 0xaa),
+    rgb(0x55, 0x55, 0x55), rgb(0x55, 0x55, 0xff), rgb(0x55, 0xff, 0x55), rgb(0x55, 0xff, 0xff),
+    rgb(0xff, 0x55, 0x55), rgb(0xff, 0x55, 0xff), rgb(0xff, 0xff, 0x55), rgb(0xff, 0xff, 0xff),
+];
+
+/// Returns the RGB value a VGA text mode color is shown as.
+pub fn color_rgb(color: Color) -> u32 {
+    PALETTE[color as usize]
+}
+
+/// Writes text to a framebuffer with a bitmap font, as many rows and
+/// columns as fit. Like `vga_buffer::Writer`, text is written to the
+/// bottom row and scrolls up, but escape sequences are not interpreted.
+pub struct TextConsole {
+    framebuffer: Framebuffer,
+    font: Font,
+    columns: usize,
+    rows: usize,
+    row_position: usize,
+    column_position: usize,
+    foreground: u32,
+    background: u32,
+}
+
+impl TextConsole {
+    pub fn new(framebuffer: Framebuffer, font: Font) -> TextConsole {
+        let columns = framebuffer.width() / GLYPH_WIDTH;
+        let rows = framebuffer.height() / GLYPH_HEIGHT;
+        TextConsole {
+            framebuffer,
+            font,
+            columns,
+            rows,
+            row_position: rows.saturating_sub(1),
+            column_position: 0,
+            foreground: color_rgb(Color::Yellow),
+            background: color_rgb(Color::Black),
+        }
+    }
+
+    /// Returns the number of characters that fit on a row.
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Returns the number of rows of text that fit on the screen.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the framebuffer, for drawing around the text.
+    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    /// Set the colors used for text written from now on.
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.foreground = color_rgb(foreground);
+        self.background = color_rgb(background);
+    }
+
+    /// Fill the screen with the background color and move to the start
+    /// of the bottom row.
+    pub fn clear(&mut self) {
+        let (width, height) = (self.framebuffer.width(), self.framebuffer.height());
+        self.framebuffer.fill_rect(0, 0, width, height, self.background);
+        self.row_position = self.rows.saturating_sub(1);
+        self.column_position = 0;
+    }
+
+    /// Write a character, or carry out a newline, carriage return or
+    /// backspace. Characters are shown with their code page 437 glyph, or
+    /// as a ■ character if there is none.
+    pub fn write_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column_position = 0,
+            '\u{8}' => self.column_position = self.column_position.saturating_sub(1),
+            c => {
+                if self.column_position >= self.columns {
+                    self.new_line();
+                }
+                self.draw_glyph(cp437::encode_or_unknown(c));
+                self.column_position += 1;
+            }
+        }
+    }
+
+    /// Write `s` starting at the given row and column, without wrapping,
+    /// scrolling or moving the write position.
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str) {
+        if row >= self.rows {
+            return;
+        }
+        let position = (self.row_position, self.column_position);
+        self.row_position = row;
+        for (col, c) in (col..self.columns).zip(s.chars()) {
+            self.column_position = col;
+            self.draw_glyph(cp437::encode_or_unknown(c));
+        }
+        self.row_position = position.0;
+        self.column_position = position.1;
+    }
+
+    /// Draw the glyph of `byte` at the write position.
+    fn draw_glyph(&mut self, byte: u8) {
+        let x = self.column_position * GLYPH_WIDTH;
+        let y = self.row_position * GLYPH_HEIGHT;
+        let glyph = *self.font.glyph(byte);
+        for (row, &line) in glyph.iter().enumerate() {
+            let mut pixels = [self.background; GLYPH_WIDTH];
+            for (col, pixel) in pixels.iter_mut().enumerate() {
+                if line & (0x80 >> col) != 0 {
+                    *pixel = self.foreground;
+                }
+            }
+            self.framebuffer.blit(x, y + row, GLYPH_WIDTH, &pixels);
+        }
+    }
+
+    /// Move to the start of the next row, scrolling everything up at the
+    /// bottom.
+    fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position + 1 < self.rows {
+            self.row_position += 1;
+        } else {
+            self.framebuffer.scroll_up(GLYPH_HEIGHT, self.background);
+        }
+    }
+}
+
+impl fmt::Write for TextConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+        Ok(())
+    }
+}
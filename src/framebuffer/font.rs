@@ -0,0 +1,96 @@
+use x86_64::{
+    instructions::port::Port,
+    structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB},
+    PhysAddr,
+};
+
+use crate::memory;
+
+/// Width of a glyph in pixels.
+pub const GLYPH_WIDTH: usize = 8;
+/// Height of a glyph in pixels.
+pub const GLYPH_HEIGHT: usize = 16;
+
+/// Where VGA plane 2, which holds the text mode font, can be read.
+const PLANE_2: u64 = 0xa0000;
+/// Bytes between glyphs in plane 2; only the first `GLYPH_HEIGHT` are used.
+const GLYPH_STRIDE: usize = 32;
+
+// Sequencer and graphics controller registers.
+const SEQ_MAP_MASK: u8 = 0x02;
+const SEQ_MEMORY_MODE: u8 = 0x04;
+const GC_READ_MAP: u8 = 0x04;
+const GC_MODE: u8 = 0x05;
+const GC_MISC: u8 = 0x06;
+
+/// A bitmap font of 256 glyphs in code page 437 order. Each glyph is a
+/// row of bits per scanline, with the leftmost pixel in the top bit.
+pub struct Font {
+    glyphs: [[u8; GLYPH_HEIGHT]; 256],
+}
+
+impl Font {
+    /// Returns the scanlines of the glyph for the code page 437 byte
+    /// `byte`.
+    pub fn glyph(&self, byte: u8) -> &[u8; GLYPH_HEIGHT] {
+        &self.glyphs[byte as usize]
+    }
+
+    /// Copy the font the VGA card uses in text mode out of plane 2 of its
+    /// memory. Must be called while still in text mode.
+    pub fn from_vga(
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<Font, MapToError<Size4KiB>> {
+        let size = 256 * GLYPH_STRIDE;
+        let plane = unsafe { memory::map_mmio(PhysAddr::new(PLANE_2), size, mapper, frame_allocator)? };
+        let plane = plane.as_ptr::<u8>();
+
+        let mut font = Font { glyphs: [[0; GLYPH_HEIGHT]; 256] };
+        with_plane_2_mapped(|| {
+            for (i, glyph) in font.glyphs.iter_mut().enumerate() {
+                for (row, line) in glyph.iter_mut().enumerate() {
+                    *line = unsafe { plane.add(i * GLYPH_STRIDE + row).read_volatile() };
+                }
+            }
+        });
+        Ok(font)
+    }
+}
+
+/// Run `f` with plane 2 readable as flat memory at `PLANE_2`, putting the
+/// text mode memory layout back afterwards.
+fn with_plane_2_mapped(f: impl FnOnce()) {
+    let mut seq_index: Port<u8> = Port::new(0x3c4);
+    let mut seq_data: Port<u8> = Port::new(0x3c5);
+    let mut gc_index: Port<u8> = Port::new(0x3ce);
+    let mut gc_data: Port<u8> = Port::new(0x3cf);
+    let read = |index: &mut Port<u8>, data: &mut Port<u8>, register: u8| unsafe {
+        index.write(register);
+        data.read()
+    };
+    let saved_map_mask = read(&mut seq_index, &mut seq_data, SEQ_MAP_MASK);
+    let saved_memory_mode = read(&mut seq_index, &mut seq_data, SEQ_MEMORY_MODE);
+    let saved_read_map = read(&mut gc_index, &mut gc_data, GC_READ_MAP);
+    let saved_mode = read(&mut gc_index, &mut gc_data, GC_MODE);
+    let saved_misc = read(&mut gc_index, &mut gc_data, GC_MISC);
+
+    let write = |index: &mut Port<u8>, data: &mut Port<u8>, register: u8, value: u8| unsafe {
+        index.write(register);
+        data.write(value);
+    };
+    // sequential access to plane 2 only, at 0xa0000
+    write(&mut seq_index, &mut seq_data, SEQ_MAP_MASK, 0x04);
+    write(&mut seq_index, &mut seq_data, SEQ_MEMORY_MODE, 0x07);
+    write(&mut gc_index, &mut gc_data, GC_READ_MAP, 0x02);
+    write(&mut gc_index, &mut gc_data, GC_MODE, 0x00);
+    write(&mut gc_index, &mut gc_data, GC_MISC, 0x04);
+
+    f();
+
+    write(&mut seq_index, &mut seq_data, SEQ_MAP_MASK, saved_map_mask);
+    write(&mut seq_index, &mut seq_data, SEQ_MEMORY_MODE, saved_memory_mode);
+    write(&mut gc_index, &mut gc_data, GC_READ_MAP, saved_read_map);
+    write(&mut gc_index, &mut gc_data, GC_MODE, saved_mode);
+    write(&mut gc_index, &mut gc_data, GC_MISC, saved_misc);
+}
@@ -0,0 +1,506 @@
+use core::fmt::Write;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+mod packet;
+
+use packet::{parse_hex, parse_le_hex, Connection, Reply, PACKET_SIZE};
+
+use crate::cmdline;
+use crate::interrupts::TrapFrame;
+use crate::memory;
+use crate::serial::{self, ComPort, Output};
+use crate::{info, warn};
+
+/// Most software breakpoints that can be set at once.
+const MAX_BREAKPOINTS: usize = 32;
+/// Number of registers in GDB's `g` packet for x86-64: the 16 general
+/// purpose registers, RIP, RFLAGS and 6 segment registers.
+const REGISTER_COUNT: usize = 24;
+/// The `int3` instruction.
+const INT3: u8 = 0xcc;
+/// Trap flag in RFLAGS, which raises a debug exception after the next
+/// instruction.
+const TRAP_FLAG: u64 = 1 << 8;
+/// Value of `PORT` while the stub is not running.
+const NO_PORT: u8 = 0xff;
+/// Stop reply telling GDB the kernel stopped with SIGTRAP.
+const STOP_REPLY: &str = "S05";
+
+/// The port GDB is connected to, as its index in `ComPort::ALL`.
+static PORT: AtomicU8 = AtomicU8::new(NO_PORT);
+/// Whether GDB let the kernel continue or step and is waiting to hear
+/// that it stopped again.
+static RESUMED: AtomicBool = AtomicBool::new(false);
+/// Software breakpoints set by GDB.
+static BREAKPOINTS: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS]> =
+    Mutex::new([None; MAX_BREAKPOINTS]);
+
+/// A software breakpoint: an `int3` written over the first byte of an
+/// instruction.
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    /// The byte the `int3` replaced.
+    original: u8,
+}
+
+/// What the stub does after executing a command.
+enum Next {
+    /// Send the reply and wait for the next command.
+    Reply,
+    /// Let the kernel run until the next trap.
+    Resume,
+    /// Acknowledge and let the kernel run, with GDB gone.
+    Detach,
+}
+
+/// Start the GDB stub if the `gdb` flag is on the kernel command line,
+/// and stop the kernel until GDB attaches. The stub talks to GDB on the
+/// port of `Output::Debugger`, which must not be the port of the console
+/// or the kernel log, e.g. `gdb debug=com2` with `qemu -serial stdio -serial tcp::1234,server` and
+/// `target remote localhost:1234` in GDB.
+pub fn init() {
+    if !cmdline::flag("gdb") {
+        return;
+    }
+    let port = match serial::output_port(Output::Debugger) {
+        Some(port) if serial::is_present(port) => port,
+        _ => {
+            warn!("No serial port for GDB; choose one with e.g. debug=com2");
+            return;
+        }
+    };
+    // other output on the port would corrupt the packets
+    if port == serial::CONSOLE || serial::output_port(Output::Log) == Some(port) {
+        warn!("Not starting GDB on {}, which the console or kernel log uses", port.name());
+        return;
+    }
+    PORT.store(port as u8, Ordering::SeqCst);
+    info!("Waiting for GDB on {}", port.name());
+    breakpoint();
+}
+
+/// Returns whether the GDB stub is running.
+pub fn is_enabled() -> bool {
+    port().is_some()
+}
+
+/// Stop and hand control to GDB, if the stub is running.
+pub fn breakpoint() {
+    if is_enabled() {
+        x86_64::instructions::interrupts::int3();
+    }
+}
+
+fn port() -> Option<ComPort> {
+    ComPort::ALL.get(PORT.load(Ordering::Relaxed) as usize).copied()
+}
+
+/// Let GDB inspect and change the kernel after a debug or breakpoint
+/// exception, until it continues or steps. Returns `false` if the stub is
+/// not running, so that the exception is handled as usual.
+pub(crate) fn handle_trap(frame: &mut TrapFrame) -> bool {
+    let port = match port() {
+        Some(port) => port,
+        None => return false,
+    };
+    // stop single stepping; `s` turns it on again
+    frame.rflags &= !TRAP_FLAG;
+    if frame.vector == 3 && is_breakpoint(frame.rip.wrapping_sub(1)) {
+        // report the breakpoint's own address, which is also where the
+        // kernel continues once GDB has put the original byte back
+        frame.rip -= 1;
+    }
+
+    let connection = Connection::new(port);
+    if RESUMED.swap(false, Ordering::SeqCst) {
+        connection.send(STOP_REPLY.as_bytes());
+    }
+    let mut buffer = [0; PACKET_SIZE];
+    loop {
+        let packet = connection.receive(&mut buffer);
+        let mut reply = Reply::new();
+        match execute(packet, frame, &mut reply) {
+            Next::Reply => connection.send(reply.as_bytes()),
+            Next::Resume => {
+                RESUMED.store(true, Ordering::SeqCst);
+                return true;
+            }
+            Next::Detach => {
+                connection.send(b"OK");
+                return true;
+            }
+        }
+    }
+}
+
+/// Execute the command in `packet`, writing the reply to `reply`. Commands
+/// the stub does not know get an empty reply, as the protocol asks for.
+fn execute(packet: &[u8], frame: &mut TrapFrame, reply: &mut Reply) -> Next {
+    let (&command, args) = match packet.split_first() {
+        Some(split) => split,
+        None => return Next::Reply,
+    };
+    match command {
+        b'?' => {
+            let _ = reply.write_str(STOP_REPLY);
+        }
+        b'g' => {
+            for n in 0..REGISTER_COUNT {
+                reply.push_le_hex(register(frame, n).unwrap_or(0), register_size(n));
+            }
+        }
+        b'G' => {
+            write_registers(frame, args);
+            let _ = reply.write_str("OK");
+        }
+        b'p' => {
+            let n = parse_hex(args).map(|n| n as usize);
+            match n.and_then(|n| register(frame, n).map(|value| (n, value))) {
+                Some((n, value)) => reply.push_le_hex(value, register_size(n)),
+                None => {
+                    let _ = reply.write_str("E00");
+                }
+            }
+        }
+        b'P' => {
+            let result = if write_register(frame, args).is_some() { "OK" } else { "E00" };
+            let _ = reply.write_str(result);
+        }
+        b'm' => read_memory(args, reply),
+        b'M' => {
+            let result = if write_memory(args).is_some() { "OK" } else { "E14" };
+            let _ = reply.write_str(result);
+        }
+        b'Z' | b'z' => set_breakpoint(command == b'Z', args, reply),
+        b'c' | b's' => {
+            if let Some(address) = parse_hex(args) {
+                frame.rip = address;
+            }
+            if command == b's' {
+                frame.rflags |= TRAP_FLAG;
+            }
+            return Next::Resume;
+        }
+        b'D' | b'k' => return Next::Detach,
+        b'q' => {
+            if args.starts_with(b"Supported") {
+                let _ = write!(reply, "PacketSize={:x}", PACKET_SIZE);
+            } else if args.starts_with(b"Attached") {
+                let _ = reply.write_str("1");
+            }
+        }
+        // there is only one thread
+        b'H' => {
+            let _ = reply.write_str("OK");
+        }
+        _ => {}
+    }
+    Next::Reply
+}
+
+
+// Registers -------------------------------------------------------------------
+
+/// Returns the size in bytes of register `n` in GDB's numbering.
+fn register_size(n: usize) -> usize {
+    if n < 17 { 8 } else { 4 }
+}
+
+/// Returns the value of register `n` in GDB's numbering.
+fn register(frame: &TrapFrame, n: usize) -> Option<u64> {
+    let mut frame = *frame;
+    match n {
+        18 => Some(frame.cs),
+        19 => Some(frame.ss),
+        // the data segment registers are not used in 64-bit mode
+        20..=23 => Some(0),
+        _ => register_mut(&mut frame, n).map(|value| *value),
+    }
+}
+
+/// Returns register `n` in GDB's numbering, if it can be changed: the
+/// general purpose registers, RIP and RFLAGS.
+fn register_mut(frame: &mut TrapFrame, n: usize) -> Option<&mut u64> {
+    Some(match n {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        _ => return None,
+    })
+}
+
+/// Set the registers from the data of a `G` packet, laid out like the
+/// reply to `g`. Registers that cannot be changed are skipped.
+fn write_registers(frame: &mut TrapFrame, mut digits: &[u8]) {
+    for n in 0..REGISTER_COUNT {
+        let len = 2 * register_size(n);
+        if digits.len() < len {
+            break;
+        }
+        let (value, rest) = digits.split_at(len);
+        digits = rest;
+        if let (Some(register), Some(value)) = (register_mut(frame, n), parse_le_hex(value)) {
+            *register = value;
+        }
+    }
+}
+
+/// Set a register from the arguments of a `P` packet, `n=value`.
+fn write_register(frame: &mut TrapFrame, args: &[u8]) -> Option<()> {
+    let separator = args.iter().position(|&byte| byte == b'=')?;
+    let n = parse_hex(&args[..separator])? as usize;
+    *register_mut(frame, n)? = parse_le_hex(&args[separator + 1..])?;
+    Some(())
+}
+
+
+// Memory ----------------------------------------------------------------------
+
+/// Returns a pointer to the byte at virtual address `address` through the
+/// mapping of the physical memory, which is writable even where `address`
+/// is not, e.g. in code. Returns `None` if `address` is not mapped.
+fn physical_ptr(address: u64) -> Option<*mut u8> {
+    let virt = VirtAddr::try_new(address).ok()?;
+    let phys = memory::translate(virt)?;
+    Some(memory::physical_to_virtual(phys)?.as_mut_ptr())
+}
+
+fn read_byte(address: u64) -> Option<u8> {
+    physical_ptr(address).map(|ptr| unsafe { ptr::read_volatile(ptr) })
+}
+
+fn write_byte(address: u64, byte: u8) -> Option<()> {
+    physical_ptr(address).map(|ptr| unsafe { ptr::write_volatile(ptr, byte) })
+}
+
+/// Parses `address,length`, the arguments of the memory and breakpoint
+/// commands.
+fn parse_range(args: &[u8]) -> Option<(u64, u64)> {
+    let separator = args.iter().position(|&byte| byte == b',')?;
+    Some((parse_hex(&args[..separator])?, parse_hex(&args[separator + 1..])?))
+}
+
+/// Reply to an `m` packet with the memory it asks for, or as much of it as
+/// is mapped.
+fn read_memory(args: &[u8], reply: &mut Reply) {
+    let (address, len) = match parse_range(args) {
+        Some(range) => range,
+        None => {
+            let _ = reply.write_str("E01");
+            return;
+        }
+    };
+    let len = len.min(PACKET_SIZE as u64 / 2);
+    for i in 0..len {
+        match read_byte(address.wrapping_add(i)) {
+            Some(byte) => reply.push_le_hex(byte as u64, 1),
+            None if i == 0 => {
+                let _ = reply.write_str("E14");
+                return;
+            }
+            None => return,
+        }
+    }
+}
+
+/// Carry out an `M` packet, `address,length:bytes`.
+fn write_memory(args: &[u8]) -> Option<()> {
+    let separator = args.iter().position(|&byte| byte == b':')?;
+    let (address, len) = parse_range(&args[..separator])?;
+    let data = &args[separator + 1..];
+    // `len` comes from GDB, so `2 * len` could overflow
+    if data.len() % 2 != 0 || (data.len() / 2) as u64 != len {
+        return None;
+    }
+    for (i, pair) in data.chunks(2).enumerate() {
+        write_byte(address.wrapping_add(i as u64), parse_hex(pair)? as u8)?;
+    }
+    Some(())
+}
+
+
+// Breakpoints -----------------------------------------------------------------
+
+/// Returns whether GDB has set a breakpoint at `address`.
+fn is_breakpoint(address: u64) -> bool {
+    BREAKPOINTS.lock().iter().flatten().any(|breakpoint| breakpoint.address == address)
+}
+
+/// Carry out a `Z` (`insert`) or `z` packet, `type,address,kind`. Only
+/// software breakpoints (type 0) are supported.
+fn set_breakpoint(insert: bool, args: &[u8], reply: &mut Reply) {
+    let args = match args {
+        [b'0', b',', args @ ..] => args,
+        _ => return,
+    };
+    let address = match parse_range(args) {
+        Some((address, _kind)) => address,
+        None => {
+            let _ = reply.write_str("E01");
+            return;
+        }
+    };
+    let result = if insert { insert_breakpoint(address) } else { remove_breakpoint(address) };
+    let _ = reply.write_str(if result.is_some() { "OK" } else { "E14" });
+}
+
+fn insert_breakpoint(address: u64) -> Option<()> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().flatten().any(|breakpoint| breakpoint.address == address) {
+        return Some(());
+    }
+    let slot = breakpoints.iter_mut().find(|slot| slot.is_none())?;
+    let original = read_byte(address)?;
+    write_byte(address, INT3)?;
+    *slot = Some(Breakpoint { address, original });
+    Some(())
+}
+
+fn remove_breakpoint(address: u64) -> Option<()> {
+    let mut breakpoints = BREAKPOINTS.lock();
+    let slot = breakpoints.iter_mut()
+        .find(|slot| matches!(slot, Some(breakpoint) if breakpoint.address == address))?;
+    if let Some(breakpoint) = slot.take() {
+        write_byte(address, breakpoint.original)?;
+    }
+    Some(())
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+/// Returns a frame whose registers can be told apart.
+#[cfg(test)]
+fn test_frame() -> TrapFrame {
+    TrapFrame {
+        rax: 0x1, rbx: 0x2, rcx: 0x3, rdx: 0x4, rsi: 0x5, rdi: 0x6, rbp: 0x7,
+        r8: 0x8, r9: 0x9, r10: 0xa, r11: 0xb, r12: 0xc, r13: 0xd, r14: 0xe, r15: 0xf,
+        vector: 3,
+        rip: 0xffff_8000_0000_1234,
+        cs: 0x8,
+        rflags: 0x202,
+        rsp: 0x10_0000,
+        ss: 0x10,
+    }
+}
+
+/// Execute `command` on `frame` and return the reply.
+#[cfg(test)]
+fn run(command: &[u8], frame: &mut TrapFrame) -> Reply {
+    let mut reply = Reply::new();
+    execute(command, frame, &mut reply);
+    reply
+}
+
+#[test_case]
+fn test_registers() {
+    let mut frame = test_frame();
+    let reply = run(b"g", &mut frame);
+    let registers = reply.as_bytes();
+    assert_eq!(registers.len(), 2 * (17 * 8 + 7 * 4));
+    assert_eq!(&registers[..16], b"0100000000000000");
+    // RSP comes after RBP, before R8
+    assert_eq!(&registers[7 * 16..8 * 16], b"0000100000000000");
+    assert_eq!(&registers[16 * 16..17 * 16], b"341200000080ffff");
+    // RFLAGS, CS, SS and the data segment registers take 4 bytes each
+    assert_eq!(&registers[17 * 16..17 * 16 + 24], b"020200000800000010000000");
+    assert!(registers[17 * 16 + 24..].iter().all(|&digit| digit == b'0'));
+
+    assert_eq!(run(b"p7", &mut frame).as_bytes(), b"0000100000000000");
+    assert_eq!(run(b"p11", &mut frame).as_bytes(), b"02020000");
+    assert_eq!(run(b"p12", &mut frame).as_bytes(), b"08000000");
+    assert_eq!(run(b"p18", &mut frame).as_bytes(), b"E00");
+
+    assert_eq!(run(b"P0=2a00000000000000", &mut frame).as_bytes(), b"OK");
+    assert_eq!(frame.rax, 0x2a);
+    // the segment registers cannot be changed
+    assert_eq!(run(b"P12=10000000", &mut frame).as_bytes(), b"E00");
+    assert_eq!(frame.cs, 0x8);
+
+    // `G` takes what `g` gives
+    let mut other = test_frame();
+    other.rbx = 0x1122_3344;
+    other.r15 = 0x55;
+    other.rflags = 0x246;
+    other.cs = 0x1b;
+    let mut command = Reply::new();
+    let _ = command.write_str("G");
+    for n in 0..REGISTER_COUNT {
+        command.push_le_hex(register(&other, n).unwrap_or(0), register_size(n));
+    }
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"OK");
+    assert_eq!((frame.rax, frame.rbx, frame.r15, frame.rflags), (0x1, 0x1122_3344, 0x55, 0x246));
+    assert_eq!(frame.cs, 0x8);
+}
+
+#[test_case]
+fn test_memory() {
+    let mut buffer = [0u8; 4];
+    let address = buffer.as_mut_ptr() as u64;
+    let mut frame = test_frame();
+
+    let mut command = Reply::new();
+    let _ = write!(command, "M{:x},4:deadbeef", address);
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"OK");
+    let written: [u8; 4] = unsafe { ptr::read_volatile(&buffer) };
+    assert_eq!(written, [0xde, 0xad, 0xbe, 0xef]);
+
+    let mut command = Reply::new();
+    let _ = write!(command, "m{:x},4", address);
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"deadbeef");
+
+    // not a canonical address
+    assert_eq!(run(b"m800000000000,1", &mut frame).as_bytes(), b"E14");
+    assert_eq!(run(b"M800000000000,1:00", &mut frame).as_bytes(), b"E14");
+    // a length that does not match the data, too large to double
+    let mut command = Reply::new();
+    let _ = write!(command, "M{:x},8000000000000000:", address);
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"E14");
+}
+
+#[test_case]
+fn test_breakpoints() {
+    // stands in for code, since the stub writes through the physical
+    // memory mapping either way
+    let mut code = [0x90u8; 2];
+    let address = code.as_mut_ptr() as u64;
+    let mut frame = test_frame();
+
+    let mut command = Reply::new();
+    let _ = write!(command, "Z0,{:x},1", address);
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"OK");
+    assert_eq!(unsafe { ptr::read_volatile(&code) }, [INT3, 0x90]);
+    assert!(is_breakpoint(address));
+
+    let mut command = Reply::new();
+    let _ = write!(command, "z0,{:x},1", address);
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"OK");
+    assert_eq!(unsafe { ptr::read_volatile(&code) }, [0x90, 0x90]);
+    assert!(!is_breakpoint(address));
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"E14");
+
+    // hardware breakpoints and watchpoints are not supported
+    let mut command = Reply::new();
+    let _ = write!(command, "Z1,{:x},1", address);
+    assert_eq!(run(command.as_bytes(), &mut frame).as_bytes(), b"");
+}
@@ -0,0 +1,184 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::spin_loop_hint;
+
+use crate::serial::{self, ComPort, Uart};
+
+/// Size of the largest packet accepted, as told to GDB.
+pub(super) const PACKET_SIZE: usize = 0x400;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// The connection to GDB over a serial port. The port is polled, since the
+/// stub runs with interrupts disabled.
+pub(super) struct Connection {
+    port: ComPort,
+}
+
+impl Connection {
+    pub(super) fn new(port: ComPort) -> Connection {
+        Connection { port }
+    }
+
+    fn read_byte(&self) -> u8 {
+        loop {
+            if let Some(byte) = serial::poll_byte(self.port) {
+                return byte;
+            }
+            spin_loop_hint();
+        }
+    }
+
+    fn write_byte(&self, byte: u8) {
+        // GDB has the port to itself, so it is not locked
+        unsafe { Uart::new(self.port.base()) }.send(byte);
+    }
+
+    /// Wait for a packet from GDB, acknowledge it and return its data.
+    /// Packets with a wrong checksum are asked for again.
+    pub(super) fn receive<'b>(&self, buffer: &'b mut [u8; PACKET_SIZE]) -> &'b [u8] {
+        loop {
+            // skip anything before the start of a packet, like stray
+            // acknowledgements
+            while self.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut too_long = false;
+            loop {
+                let byte = self.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+                match buffer.get_mut(len) {
+                    Some(slot) => *slot = byte,
+                    None => too_long = true,
+                }
+                len += 1;
+            }
+            let digits = [self.read_byte(), self.read_byte()];
+            if !too_long && checksum_matches(&buffer[..len], digits) {
+                self.write_byte(b'+');
+                return &buffer[..len];
+            }
+            self.write_byte(b'-');
+        }
+    }
+
+    /// Send a packet to GDB, again until it is acknowledged.
+    pub(super) fn send(&self, data: &[u8]) {
+        let sum = checksum(data);
+        loop {
+            self.write_byte(b'$');
+            for &byte in data {
+                self.write_byte(byte);
+            }
+            self.write_byte(b'#');
+            self.write_byte(HEX_DIGITS[(sum >> 4) as usize]);
+            self.write_byte(HEX_DIGITS[(sum & 0xf) as usize]);
+
+            loop {
+                match self.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Returns the checksum of the data of a packet: the sum of its bytes,
+/// modulo 256.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Returns whether `digits`, the two hex digits following the `#` at the
+/// end of a packet, are the checksum of the packet's `data`.
+fn checksum_matches(data: &[u8], digits: [u8; 2]) -> bool {
+    parse_hex(&digits) == Some(checksum(data) as u64)
+}
+
+/// A reply to GDB being put together. Text that does not fit in a packet
+/// is dropped.
+pub(super) struct Reply {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    pub(super) fn new() -> Reply {
+        Reply { data: [0; PACKET_SIZE], len: 0 }
+    }
+
+    pub(super) fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    /// Append the lowest `size` bytes of `value` in hex, least significant
+    /// byte first, as GDB expects register values and memory.
+    pub(super) fn push_le_hex(&mut self, value: u64, size: usize) {
+        for i in 0..size {
+            let _ = write!(self, "{:02x}", (value >> (8 * i)) as u8);
+        }
+    }
+}
+
+impl Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(PACKET_SIZE - self.len);
+        self.data[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Parses a number written in hex, most significant digit first, as GDB
+/// writes addresses and lengths.
+pub(super) fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0, |value, &digit| {
+        Some(value << 4 | (digit as char).to_digit(16)? as u64)
+    })
+}
+
+/// Parses a value of up to 8 bytes written in hex, least significant byte
+/// first, as GDB writes register values.
+pub(super) fn parse_le_hex(digits: &[u8]) -> Option<u64> {
+    if digits.len() % 2 != 0 || digits.len() > 16 {
+        return None;
+    }
+    digits.chunks(2).enumerate().try_fold(0, |value, (i, pair)| {
+        Some(value | parse_hex(pair)? << (8 * i))
+    })
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_hex() {
+    assert_eq!(parse_hex(b"ffff8000001a2b3c"), Some(0xffff_8000_001a_2b3c));
+    assert_eq!(parse_hex(b"1G"), None);
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_le_hex(b"3412"), Some(0x1234));
+    assert_eq!(parse_le_hex(b"123"), None);
+
+    let mut reply = Reply::new();
+    let _ = write!(reply, "S{:02x}", 5);
+    reply.push_le_hex(0x1234, 4);
+    assert_eq!(reply.as_bytes(), b"S0534120000");
+}
+
+#[test_case]
+fn test_checksum() {
+    assert_eq!(checksum(b""), 0);
+    assert_eq!(checksum(b"OK"), 0x9a);
+    // the sum wraps around
+    assert_eq!(checksum(b"qSupported:multiprocess+"), 0xc6);
+    assert!(checksum_matches(b"OK", *b"9a"));
+    assert!(checksum_matches(b"OK", *b"9A"));
+    assert!(!checksum_matches(b"OK", *b"9b"));
+    assert!(!checksum_matches(b"OK", *b"x9"));
+}
@@ -1,10 +1,10 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use lazy_static::lazy_static;
 use spin::Mutex;
 
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259_simple::ChainedPics;
 use x86_64::{
-    instructions::port::Port,
     registers::control::Cr2,
     structures::idt::{
         InterruptDescriptorTable,
@@ -13,9 +13,25 @@ use x86_64::{
     },
 };
 
-use crate::{print, println};
+use crate::apic;
+use crate::crash;
 use crate::gdt;
-use crate::hlt_loop;
+
+mod fallback;
+mod irq;
+mod trap;
+
+pub use fallback::{
+    exception_count, exception_name, unhandled_count, unhandled_total, NUM_EXCEPTIONS,
+};
+pub use irq::{
+    irq_count, irq_counts, is_masked, mask_irq, register_irq, spurious_counts,
+    unmask_irq, unregister_irq, IrqError, IrqHandler, NUM_IRQS,
+};
+pub use trap::TrapFrame;
+
+/// Number of spurious interrupts raised by the local APIC.
+static APIC_SPURIOUS: AtomicU64 = AtomicU64::new(0);
 
 lazy_static! {
     /// Creates an Interrupt Descriptor Table used to handle various
@@ -23,8 +39,12 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
 
-        // breakpoint exception
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        // catch-all handlers for every exception and vector, so nothing
+        // unexpected escalates to a general protection or double fault
+        fallback::set_handler_fns(&mut idt);
+
+        // debug and breakpoint exceptions, which the GDB stub hooks into
+        trap::set_handler_fns(&mut idt);
 
         // page fault exception
         idt.page_fault.set_handler_fn(page_fault_handler);
@@ -35,13 +55,17 @@ lazy_static! {
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
 
-        // timer interrupt
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler);
+        // legacy PIC interrupts, dispatched to handlers registered with
+        // `register_irq`
+        irq::set_handler_fns(&mut idt);
+
+        // local APIC timer interrupt
+        idt[InterruptIndex::ApicTimer.as_usize()]
+            .set_handler_fn(apic_timer_interrupt_handler);
 
-        // keyboard interrupt
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler);
+        // local APIC spurious interrupt
+        idt[InterruptIndex::ApicSpurious.as_usize()]
+            .set_handler_fn(apic_spurious_interrupt_handler);
 
         idt
     };
@@ -52,70 +76,80 @@ pub fn init_idt() {
     IDT.load();
 }
 
-/// Handles breakpoint exceptions.
-extern "x86-interrupt" fn breakpoint_handler(
-    stack_frame: &mut InterruptStackFrame)
-{
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
 /// Handles page faults.
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+    fallback::count_exception(14);
+    crash::record_exception(14, Some(error_code.bits()), stack_frame);
+    panic!("EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        Cr2::read(), error_code, stack_frame);
 }
 
 /// Handles double faults.
 extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: &mut InterruptStackFrame, _error_code: u64) -> !
+    stack_frame: &mut InterruptStackFrame, error_code: u64) -> !
 {
+    fallback::count_exception(8);
+    crash::record_exception(8, Some(error_code), stack_frame);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-/// Handles timer hardware interrupts.
-extern "x86-interrupt" fn timer_interrupt_handler(
+/// Handles local APIC timer interrupts.
+extern "x86-interrupt" fn apic_timer_interrupt_handler(
     _stack_frame: &mut InterruptStackFrame)
 {
-    // print!(".");
-
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    apic::handle_timer_interrupt();
+    apic::end_of_interrupt();
 }
 
-/// Handles keyboard interrupts.
-extern "x86-interrupt" fn keyboard_interrupt_handler(
+/// Handles spurious interrupts from the local APIC. These must not be
+/// acknowledged with an EOI.
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(
     _stack_frame: &mut InterruptStackFrame)
 {
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
-                HandleControl::Ignore));
-    }
+    APIC_SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
-            }
-        }
+// Statistics ------------------------------------------------------------------
+
+/// A snapshot of the interrupt counters.
+#[derive(Debug, Clone)]
+pub struct InterruptStats {
+    /// Interrupts received on each legacy IRQ line, excluding spurious ones.
+    pub irqs: [u64; NUM_IRQS],
+    /// Spurious interrupts raised by the master PIC (on IRQ 7).
+    pub spurious_master: u64,
+    /// Spurious interrupts raised by the slave PIC (on IRQ 15).
+    pub spurious_slave: u64,
+    /// Spurious interrupts raised by the local APIC.
+    pub spurious_apic: u64,
+    /// Interrupts from the local APIC timer.
+    pub apic_timer: u64,
+    /// Number of times each CPU exception has occurred.
+    pub exceptions: [u64; NUM_EXCEPTIONS],
+    /// Interrupts received on vectors with no handler installed.
+    pub unhandled: u64,
+}
+
+/// Returns a snapshot of all interrupt counters.
+pub fn stats() -> InterruptStats {
+    let (spurious_master, spurious_slave) = spurious_counts();
+    let mut exceptions = [0; NUM_EXCEPTIONS];
+    for (vector, count) in exceptions.iter_mut().enumerate() {
+        *count = exception_count(vector as u8);
     }
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    InterruptStats {
+        irqs: irq_counts(),
+        spurious_master,
+        spurious_slave,
+        spurious_apic: APIC_SPURIOUS.load(Ordering::Relaxed),
+        apic_timer: apic::timer_interrupts(),
+        exceptions,
+        unhandled: unhandled_total(),
     }
 }
 
@@ -124,6 +158,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+pub const APIC_OFFSET: u8 = PIC_2_OFFSET + 8;
 
 /// Abstraction for two Intel 8259 PIC (programmable interrupt controller)
 /// chips chained together, which control hardware interrupts.
@@ -131,18 +166,41 @@ pub static PICS: Mutex<ChainedPics> = Mutex::new(unsafe {
     ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
 });
 
-/// An index used to identify of which line of the PIC is sending
-/// an interrupt.
-#[derive(Debug, Clone, Copy)]
+/// Initialize the PICs with every IRQ line masked except the cascade
+/// line. Lines are unmasked as handlers are registered for them.
+pub fn init_pics() {
+    unsafe { PICS.lock().initialize() };
+    irq::mask_all();
+}
+
+/// An index used to identify of which line of the PIC (or which local
+/// APIC source) is sending an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Cascade,
+    Com2,
+    Com1,
+    Lpt2,
+    Floppy,
+    Lpt1,
+    Rtc = PIC_2_OFFSET,
+    Acpi,
+    Free10,
+    Free11,
+    Mouse,
+    Fpu,
+    PrimaryAta,
+    SecondaryAta,
+    ApicTimer = APIC_OFFSET,
+    ApicSpurious = 0xff,
 }
 
 impl InterruptIndex {
     /// Converts InterruptIndex to u8.
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -150,6 +208,15 @@ impl InterruptIndex {
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
+
+    /// Returns the legacy IRQ line (0-15) for interrupts that come from
+    /// the PICs.
+    ///
+    /// Panics if called on an interrupt delivered by the local APIC.
+    pub fn as_irq(self) -> u8 {
+        assert!(self.as_u8() < APIC_OFFSET, "{:?} is not a PIC interrupt", self);
+        self.as_u8() - PIC_1_OFFSET
+    }
 }
 
 
@@ -159,4 +226,24 @@ impl InterruptIndex {
 fn test_breakpoint_exception() {
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_register_unregister_irq() {
+    fn handler(_irq: u8) {}
+    let irq = InterruptIndex::Free10.as_irq();
+
+    assert_eq!(register_irq(irq, handler), Ok(()));
+    assert_eq!(register_irq(irq, handler), Err(IrqError::AlreadyRegistered));
+    assert_eq!(unregister_irq(irq, handler), Ok(()));
+    assert_eq!(unregister_irq(irq, handler), Err(IrqError::NotRegistered));
+    assert_eq!(register_irq(NUM_IRQS as u8, handler), Err(IrqError::InvalidIrq));
+}
+
+#[test_case]
+fn test_breakpoint_counted() {
+    let before = exception_count(3);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(exception_count(3), before + 1);
+    assert_eq!(stats().exceptions[3], before + 1);
+}
@@ -0,0 +1,217 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use crate::apic;
+use crate::crash;
+use crate::emergency;
+use crate::hlt_loop;
+use crate::{try_println, warn};
+
+/// Number of CPU exception vectors reserved by the architecture.
+pub const NUM_EXCEPTIONS: usize = 32;
+
+/// Names of the CPU exceptions, indexed by vector.
+const EXCEPTION_NAMES: [&str; NUM_EXCEPTIONS] = [
+    "DIVIDE ERROR",
+    "DEBUG",
+    "NON-MASKABLE INTERRUPT",
+    "BREAKPOINT",
+    "OVERFLOW",
+    "BOUND RANGE EXCEEDED",
+    "INVALID OPCODE",
+    "DEVICE NOT AVAILABLE",
+    "DOUBLE FAULT",
+    "COPROCESSOR SEGMENT OVERRUN",
+    "INVALID TSS",
+    "SEGMENT NOT PRESENT",
+    "STACK-SEGMENT FAULT",
+    "GENERAL PROTECTION FAULT",
+    "PAGE FAULT",
+    "RESERVED",
+    "X87 FLOATING-POINT",
+    "ALIGNMENT CHECK",
+    "MACHINE CHECK",
+    "SIMD FLOATING-POINT",
+    "VIRTUALIZATION",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "SECURITY EXCEPTION",
+    "RESERVED",
+];
+
+/// Number of times each CPU exception has occurred.
+static EXCEPTIONS: [AtomicU64; NUM_EXCEPTIONS] = [ZERO; NUM_EXCEPTIONS];
+/// Number of interrupts received on each vector with no handler installed.
+static UNHANDLED: [AtomicU64; 256] = [ZERO; 256];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+
+/// Returns the name of the given CPU exception vector.
+pub fn exception_name(vector: u8) -> &'static str {
+    EXCEPTION_NAMES.get(vector as usize).copied().unwrap_or("NOT AN EXCEPTION")
+}
+
+/// Returns how many times the given CPU exception has occurred.
+pub fn exception_count(vector: u8) -> u64 {
+    EXCEPTIONS.get(vector as usize)
+        .map(|count| count.load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
+/// Returns how many interrupts have arrived on `vector` while it had no
+/// handler installed.
+pub fn unhandled_count(vector: u8) -> u64 {
+    UNHANDLED[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Returns how many interrupts have arrived on vectors with no handler.
+pub fn unhandled_total() -> u64 {
+    UNHANDLED.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+}
+
+/// Record that the given CPU exception has occurred.
+pub(super) fn count_exception(vector: u8) {
+    EXCEPTIONS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Common handler for CPU exceptions that have no dedicated handler.
+/// Traps and NMIs are logged and resumed; faults cannot be resumed
+/// without fixing their cause, so they panic. An NMI while the kernel is
+/// panicking is another CPU's request to halt.
+fn unhandled_exception(vector: u8, error_code: Option<u64>,
+    stack_frame: &InterruptStackFrame)
+{
+    count_exception(vector);
+    match vector {
+        2 if emergency::is_active() => hlt_loop(),
+        1 | 2 | 4 => {
+            try_println!("EXCEPTION: {} (vector {})\n{:#?}",
+                exception_name(vector), vector, stack_frame);
+        }
+        _ => {
+            crash::record_exception(vector, error_code, stack_frame);
+            panic!("EXCEPTION: {} (vector {}), error code {:?}\n{:#?}",
+                exception_name(vector), vector, error_code, stack_frame)
+        }
+    }
+}
+
+/// Common handler for interrupt vectors that have no handler installed.
+fn unhandled_interrupt(vector: u8, _stack_frame: &InterruptStackFrame) {
+    UNHANDLED[vector as usize].fetch_add(1, Ordering::Relaxed);
+    warn!("Unhandled interrupt on vector {}", vector);
+    // nothing outside the PIC range has a handler to acknowledge it, so if
+    // this came through the local APIC it still needs an EOI. Software
+    // interrupts and stray vectors must not get one, since it would end
+    // whichever APIC interrupt is in service instead.
+    if apic::is_in_service(vector) {
+        apic::end_of_interrupt();
+    }
+}
+
+/// Defines one stub per CPU exception without an error code that forwards
+/// to `unhandled_exception`, and installs it in the IDT.
+macro_rules! set_exception_stubs {
+    ($idt:ident; $($field:ident = $vector:literal),*) => {
+        $({
+            extern "x86-interrupt" fn stub(stack_frame: &mut InterruptStackFrame) {
+                unhandled_exception($vector, None, stack_frame);
+            }
+            $idt.$field.set_handler_fn(stub);
+        })*
+    };
+}
+
+/// Like `set_exception_stubs`, for CPU exceptions that push an error code.
+macro_rules! set_exception_stubs_with_error_code {
+    ($idt:ident; $($field:ident = $vector:literal),*) => {
+        $({
+            extern "x86-interrupt" fn stub(stack_frame: &mut InterruptStackFrame,
+                error_code: u64)
+            {
+                unhandled_exception($vector, Some(error_code), stack_frame);
+            }
+            $idt.$field.set_handler_fn(stub);
+        })*
+    };
+}
+
+/// Handler for machine checks. The CPU cannot resume after one, so the
+/// IDT wants a diverging handler rather than one of the generic stubs.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: &mut InterruptStackFrame) -> ! {
+    // panics, like every other fault
+    unhandled_exception(18, None, stack_frame);
+    hlt_loop()
+}
+
+/// Defines one stub per interrupt vector in rows of 16 (given by the high
+/// nibble of the vector) that forwards to `unhandled_interrupt`, and
+/// installs it in the IDT.
+macro_rules! set_vector_stubs {
+    ($idt:ident; $($row:literal),*) => {
+        $( set_vector_stubs!(@row $idt, $row); )*
+    };
+    (@row $idt:ident, $row:literal) => {
+        set_vector_stubs!(@one $idt, $row, 0x0);
+        set_vector_stubs!(@one $idt, $row, 0x1);
+        set_vector_stubs!(@one $idt, $row, 0x2);
+        set_vector_stubs!(@one $idt, $row, 0x3);
+        set_vector_stubs!(@one $idt, $row, 0x4);
+        set_vector_stubs!(@one $idt, $row, 0x5);
+        set_vector_stubs!(@one $idt, $row, 0x6);
+        set_vector_stubs!(@one $idt, $row, 0x7);
+        set_vector_stubs!(@one $idt, $row, 0x8);
+        set_vector_stubs!(@one $idt, $row, 0x9);
+        set_vector_stubs!(@one $idt, $row, 0xa);
+        set_vector_stubs!(@one $idt, $row, 0xb);
+        set_vector_stubs!(@one $idt, $row, 0xc);
+        set_vector_stubs!(@one $idt, $row, 0xd);
+        set_vector_stubs!(@one $idt, $row, 0xe);
+        set_vector_stubs!(@one $idt, $row, 0xf);
+    };
+    (@one $idt:ident, $row:literal, $col:literal) => {{
+        extern "x86-interrupt" fn stub(stack_frame: &mut InterruptStackFrame) {
+            unhandled_interrupt($row * 16 + $col, stack_frame);
+        }
+        $idt[$row * 16 + $col].set_handler_fn(stub);
+    }};
+}
+
+/// Install fallback handlers for every CPU exception and every interrupt
+/// vector from 48 upwards. Vectors with a real handler are overwritten by
+/// the caller afterwards; vectors 32-47 are covered by the IRQ stubs.
+pub(super) fn set_handler_fns(idt: &mut InterruptDescriptorTable) {
+    set_exception_stubs!(idt;
+        divide_error = 0,
+        debug = 1,
+        non_maskable_interrupt = 2,
+        overflow = 4,
+        bound_range_exceeded = 5,
+        invalid_opcode = 6,
+        device_not_available = 7,
+        x87_floating_point = 16,
+        simd_floating_point = 19,
+        virtualization = 20
+    );
+    set_exception_stubs_with_error_code!(idt;
+        invalid_tss = 10,
+        segment_not_present = 11,
+        stack_segment_fault = 12,
+        general_protection_fault = 13,
+        alignment_check = 17,
+        security_exception = 30
+    );
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    set_vector_stubs!(idt;
+        0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xa, 0xb, 0xc, 0xd, 0xe, 0xf);
+}
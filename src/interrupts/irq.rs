@@ -0,0 +1,265 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::{interrupts, port::Port},
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+};
+
+use super::{InterruptIndex, PICS, PIC_1_OFFSET};
+
+/// Number of legacy IRQ lines handled by the two chained PICs.
+pub const NUM_IRQS: usize = 16;
+
+/// Maximum number of handlers that can share a single IRQ line.
+const MAX_HANDLERS_PER_IRQ: usize = 4;
+
+/// A function called (in interrupt context, with interrupts disabled)
+/// whenever its IRQ line fires. It receives the IRQ number, so one
+/// function can serve several lines. Handlers must not send an EOI;
+/// that is done once all handlers for the line have run.
+pub type IrqHandler = fn(irq: u8);
+
+/// Errors that can occur when registering or unregistering IRQ handlers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The IRQ number is not in the range 0-15.
+    InvalidIrq,
+    /// The handler is already registered for this IRQ.
+    AlreadyRegistered,
+    /// The handler is not registered for this IRQ.
+    NotRegistered,
+    /// All `MAX_HANDLERS_PER_IRQ` slots for this IRQ are in use.
+    NoFreeSlot,
+}
+
+/// The handlers registered for each IRQ line.
+static HANDLERS: Mutex<[[Option<IrqHandler>; MAX_HANDLERS_PER_IRQ]; NUM_IRQS]> =
+    Mutex::new([[None; MAX_HANDLERS_PER_IRQ]; NUM_IRQS]);
+
+/// Number of interrupts received on each IRQ line.
+static COUNTS: [AtomicU64; NUM_IRQS] = [ZERO; NUM_IRQS];
+/// Number of spurious interrupts raised by the master PIC (on IRQ 7).
+static SPURIOUS_MASTER: AtomicU64 = AtomicU64::new(0);
+/// Number of spurious interrupts raised by the slave PIC (on IRQ 15).
+static SPURIOUS_SLAVE: AtomicU64 = AtomicU64::new(0);
+
+/// OCW3 command to make the next read of a PIC's command port return its
+/// in-service register.
+const PIC_READ_ISR: u8 = 0x0b;
+/// Non-specific end-of-interrupt command.
+const PIC_EOI: u8 = 0x20;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+
+/// Register `handler` to be called whenever `irq` fires, and unmask the
+/// line in the PICs. Several handlers may share one line; they are called
+/// in the order they were registered.
+///
+/// Only the 16 legacy PIC lines are supported so far. Vectors delivered by
+/// the local APIC, like `InterruptIndex::ApicTimer`, have fixed handlers in
+/// the IDT and cannot be registered here.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    if irq as usize >= NUM_IRQS {
+        return Err(IrqError::InvalidIrq);
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slots = &mut handlers[irq as usize];
+        if slots.iter().flatten().any(|&h| h as usize == handler as usize) {
+            return Err(IrqError::AlreadyRegistered);
+        }
+        let free_slot = slots.iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(IrqError::NoFreeSlot)?;
+        *free_slot = Some(handler);
+        Ok(())
+    })?;
+
+    unmask_irq(irq);
+    Ok(())
+}
+
+/// Remove a handler previously registered with `register_irq`. The line
+/// is masked again once its last handler has been removed.
+pub fn unregister_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    if irq as usize >= NUM_IRQS {
+        return Err(IrqError::InvalidIrq);
+    }
+
+    let now_unused = interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slots = &mut handlers[irq as usize];
+        let slot = slots.iter_mut()
+            .find(|slot| matches!(slot, Some(h) if *h as usize == handler as usize))
+            .ok_or(IrqError::NotRegistered)?;
+        *slot = None;
+        Ok(slots.iter().all(|slot| slot.is_none()))
+    })?;
+
+    if now_unused && irq != InterruptIndex::Cascade.as_irq() {
+        mask_irq(irq);
+    }
+    Ok(())
+}
+
+/// Returns how many interrupts have been received on `irq`.
+pub fn irq_count(irq: u8) -> u64 {
+    COUNTS.get(irq as usize)
+        .map(|count| count.load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
+/// Returns how many interrupts have been received on each IRQ line.
+pub fn irq_counts() -> [u64; NUM_IRQS] {
+    let mut counts = [0; NUM_IRQS];
+    for (irq, count) in COUNTS.iter().enumerate() {
+        counts[irq] = count.load(Ordering::Relaxed);
+    }
+    counts
+}
+
+/// Returns how many spurious interrupts the (master, slave) PICs have
+/// raised.
+pub fn spurious_counts() -> (u64, u64) {
+    (SPURIOUS_MASTER.load(Ordering::Relaxed), SPURIOUS_SLAVE.load(Ordering::Relaxed))
+}
+
+/// Checks whether an interrupt on IRQ 7 or 15 is spurious, i.e. the PIC
+/// raised it but the line is no longer in service by the time the CPU
+/// acknowledged it. Spurious interrupts must not get an EOI from the PIC
+/// that raised them, but one on the slave still needs an EOI for the
+/// cascade line on the master.
+fn is_spurious(irq: u8) -> bool {
+    let (command_port, counter) = match irq {
+        7 => (0x20, &SPURIOUS_MASTER),
+        15 => (0xa0, &SPURIOUS_SLAVE),
+        _ => return false,
+    };
+
+    let _pics = PICS.lock();
+    let mut command: Port<u8> = Port::new(command_port);
+    let in_service = unsafe {
+        command.write(PIC_READ_ISR);
+        command.read()
+    };
+    if in_service & (1 << 7) != 0 {
+        return false;
+    }
+
+    counter.fetch_add(1, Ordering::Relaxed);
+    if irq == 15 {
+        unsafe { Port::<u8>::new(0x20).write(PIC_EOI) };
+    }
+    true
+}
+
+/// Common entry point for all IRQs: counts the interrupt, runs every
+/// registered handler and then notifies the PICs.
+fn dispatch(irq: u8) {
+    if is_spurious(irq) {
+        return;
+    }
+
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+
+    // copy the handlers out so that the lock is not held while they run,
+    // which lets a handler (un)register handlers itself
+    let handlers = HANDLERS.lock()[irq as usize];
+    for handler in handlers.iter().flatten() {
+        handler(irq);
+    }
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq);
+    }
+}
+
+/// Defines one `x86-interrupt` stub per IRQ that forwards to `dispatch`,
+/// and installs it in the IDT.
+macro_rules! set_irq_stubs {
+    ($idt:ident; $($irq:literal),*) => {
+        $({
+            extern "x86-interrupt" fn stub(_stack_frame: &mut InterruptStackFrame) {
+                dispatch($irq);
+            }
+            $idt[(PIC_1_OFFSET + $irq) as usize].set_handler_fn(stub);
+        })*
+    };
+}
+
+/// Install the stubs for all 16 legacy IRQs in the IDT.
+pub(super) fn set_handler_fns(idt: &mut InterruptDescriptorTable) {
+    set_irq_stubs!(idt; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+}
+
+
+// Masking ---------------------------------------------------------------------
+
+/// Returns the data port of the PIC handling the given IRQ line, and the
+/// bit for that line in the PIC's interrupt mask.
+fn pic_mask_port(irq: u8) -> (Port<u8>, u8) {
+    if irq < 8 {
+        (Port::new(0x21), 1 << irq)
+    } else {
+        (Port::new(0xa1), 1 << (irq - 8))
+    }
+}
+
+/// Unmask the given IRQ line (0-15) so the PICs deliver its interrupts.
+/// Lines on the slave PIC also unmask the cascade line on the master.
+pub fn unmask_irq(irq: u8) {
+    interrupts::without_interrupts(|| {
+        let _pics = PICS.lock();
+        let (mut port, bit) = pic_mask_port(irq);
+        unsafe {
+            let mask = port.read();
+            port.write(mask & !bit);
+        }
+        if irq >= 8 {
+            let (mut port, bit) = pic_mask_port(InterruptIndex::Cascade.as_irq());
+            unsafe {
+                let mask = port.read();
+                port.write(mask & !bit);
+            }
+        }
+    });
+}
+
+/// Mask the given IRQ line (0-15) so the PICs no longer deliver its
+/// interrupts.
+pub fn mask_irq(irq: u8) {
+    interrupts::without_interrupts(|| {
+        let _pics = PICS.lock();
+        let (mut port, bit) = pic_mask_port(irq);
+        unsafe {
+            let mask = port.read();
+            port.write(mask | bit);
+        }
+    });
+}
+
+/// Returns whether the given IRQ line (0-15) is currently masked.
+pub fn is_masked(irq: u8) -> bool {
+    interrupts::without_interrupts(|| {
+        let _pics = PICS.lock();
+        let (mut port, bit) = pic_mask_port(irq);
+        unsafe { port.read() & bit != 0 }
+    })
+}
+
+/// Mask every IRQ line except the cascade line.
+pub(super) fn mask_all() {
+    interrupts::without_interrupts(|| {
+        let _pics = PICS.lock();
+        let (mut master, cascade_bit) = pic_mask_port(InterruptIndex::Cascade.as_irq());
+        let (mut slave, _) = pic_mask_port(8);
+        unsafe {
+            master.write(0xff & !cascade_bit);
+            slave.write(0xff);
+        }
+    });
+}
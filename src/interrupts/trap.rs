@@ -0,0 +1,127 @@
+use core::mem;
+
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable};
+
+use super::fallback::{count_exception, exception_name};
+use crate::gdb;
+use crate::try_println;
+
+/// The registers of the code interrupted by a debug or breakpoint
+/// exception, as saved on the stack by `trap_common`, lowest address
+/// first. Changes to them take effect when the handler returns.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    /// The exception vector, pushed by the entry point.
+    pub vector: u64,
+    // pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// Entry points for the debug and breakpoint exceptions. Unlike the
+// `x86-interrupt` handlers, they save all general purpose registers in a
+// `TrapFrame`, so that a debugger can read and change them.
+//
+// On entry the CPU has aligned the stack to 16 bytes and pushed 5 words,
+// so after the vector and 15 registers one more word of padding is needed
+// to call `trap_dispatch` with an aligned stack.
+global_asm!(r#"
+.section .text
+.global trap_debug_entry
+trap_debug_entry:
+    pushq $1
+    jmp trap_common
+
+.global trap_breakpoint_entry
+trap_breakpoint_entry:
+    pushq $3
+    jmp trap_common
+
+trap_common:
+    pushq %r15
+    pushq %r14
+    pushq %r13
+    pushq %r12
+    pushq %r11
+    pushq %r10
+    pushq %r9
+    pushq %r8
+    pushq %rbp
+    pushq %rdi
+    pushq %rsi
+    pushq %rdx
+    pushq %rcx
+    pushq %rbx
+    pushq %rax
+    movq %rsp, %rdi
+    subq $8, %rsp
+    cld
+    call trap_dispatch
+    addq $8, %rsp
+    popq %rax
+    popq %rbx
+    popq %rcx
+    popq %rdx
+    popq %rsi
+    popq %rdi
+    popq %rbp
+    popq %r8
+    popq %r9
+    popq %r10
+    popq %r11
+    popq %r12
+    popq %r13
+    popq %r14
+    popq %r15
+    addq $8, %rsp
+    iretq
+"#);
+
+extern "C" {
+    fn trap_debug_entry();
+    fn trap_breakpoint_entry();
+}
+
+/// Installs the entry points for the debug and breakpoint exceptions in
+/// the IDT, replacing the fallback handler of the debug exception.
+pub(super) fn set_handler_fns(idt: &mut InterruptDescriptorTable) {
+    // the entry points are not `x86-interrupt` functions, but like them
+    // they return with `iretq`, which is all the IDT cares about
+    unsafe {
+        idt.debug.set_handler_fn(
+            mem::transmute::<unsafe extern "C" fn(), HandlerFunc>(trap_debug_entry));
+        idt.breakpoint.set_handler_fn(
+            mem::transmute::<unsafe extern "C" fn(), HandlerFunc>(trap_breakpoint_entry));
+    }
+}
+
+/// Handles debug and breakpoint exceptions, called by `trap_common`. They
+/// are passed to the GDB stub if it is running, and otherwise logged and
+/// resumed.
+#[no_mangle]
+extern "C" fn trap_dispatch(frame: &mut TrapFrame) {
+    let vector = frame.vector as u8;
+    count_exception(vector);
+    if !gdb::handle_trap(frame) {
+        try_println!("EXCEPTION: {} (vector {})\n{:#x?}", exception_name(vector), vector, frame);
+    }
+}
@@ -0,0 +1,454 @@
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use core::task::Waker;
+
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use pc_keyboard::{
+    HandleControl, KeyEvent, KeyState, Keyboard, ScancodeSet1, ScancodeSet2,
+};
+use x86_64::instructions::{interrupts, port::Port};
+
+pub use pc_keyboard::{DecodedKey, KeyCode};
+
+mod layouts;
+
+pub use layouts::Layout;
+use layouts::DynamicLayout;
+
+use crate::cmdline;
+use crate::interrupts::{self as irq, InterruptIndex};
+use crate::warn;
+use crate::ps2::{self, Ps2Port};
+use crate::queue::ByteQueue;
+use crate::vga_buffer::{self, CONSOLE_COUNT};
+
+/// Reply from the keyboard acknowledging a command.
+const RESPONSE_ACK: u8 = 0xfa;
+/// Reply from the keyboard asking for the last byte to be sent again.
+const RESPONSE_RESEND: u8 = 0xfe;
+
+/// Keyboard command: set the LEDs to the state in the next byte.
+const COMMAND_SET_LEDS: u8 = 0xed;
+/// Keyboard command: select the scancode set given in the next byte.
+const COMMAND_SCANCODE_SET: u8 = 0xf0;
+
+// Bits of `MODIFIERS`.
+const LEFT_SHIFT: u16 = 1 << 0;
+const RIGHT_SHIFT: u16 = 1 << 1;
+const LEFT_CTRL: u16 = 1 << 2;
+const RIGHT_CTRL: u16 = 1 << 3;
+const LEFT_ALT: u16 = 1 << 4;
+const RIGHT_ALT: u16 = 1 << 5;
+const CAPS_LOCK: u16 = 1 << 6;
+const NUM_LOCK: u16 = 1 << 7;
+const SCROLL_LOCK: u16 = 1 << 8;
+
+/// Most key presses queued for one virtual console.
+const KEY_QUEUE_SIZE: usize = 32;
+
+/// Raw scancodes read by the interrupt handler, waiting to be decoded.
+static SCANCODES: ByteQueue = ByteQueue::new();
+/// Decoded key presses, for each virtual console.
+static KEYS: Mutex<[KeyQueue; CONSOLE_COUNT]> = Mutex::new([KeyQueue::EMPTY; CONSOLE_COUNT]);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_WAKER: AtomicWaker = AtomicWaker::new();
+/// Wakers of the tasks reading key presses on each virtual console.
+static KEY_WAKERS: [AtomicWaker; CONSOLE_COUNT] = [NO_WAKER; CONSOLE_COUNT];
+/// State of the modifier and lock keys, as a combination of the bits above.
+/// Kept in an atomic so layouts can query it while the decoder is locked.
+static MODIFIERS: AtomicU16 = AtomicU16::new(0);
+/// Whether a lock key was toggled since the LEDs were last updated.
+static LEDS_DIRTY: AtomicBool = AtomicBool::new(false);
+
+/// The scancode sets the keyboard can be decoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSetKind {
+    /// IBM PC XT scancodes. Keyboards send these when the PS/2 controller
+    /// translates set 2, which is how the firmware sets things up.
+    Set1,
+    /// IBM PC AT scancodes, received untranslated.
+    Set2,
+}
+
+/// Decoder turning scancodes into key presses, for either scancode set.
+enum Decoder {
+    Set1(Keyboard<DynamicLayout, ScancodeSet1>),
+    Set2(Keyboard<DynamicLayout, ScancodeSet2>),
+}
+
+impl Decoder {
+    /// Creates a decoder for the given scancode set.
+    fn new(set: ScancodeSetKind) -> Decoder {
+        let handle_ctrl = HandleControl::MapLettersToUnicode;
+        match set {
+            ScancodeSetKind::Set1 =>
+                Decoder::Set1(Keyboard::new(DynamicLayout, ScancodeSet1, handle_ctrl)),
+            ScancodeSetKind::Set2 =>
+                Decoder::Set2(Keyboard::new(DynamicLayout, ScancodeSet2, handle_ctrl)),
+        }
+    }
+
+    /// Returns the scancode set this decoder expects.
+    fn set(&self) -> ScancodeSetKind {
+        match self {
+            Decoder::Set1(_) => ScancodeSetKind::Set1,
+            Decoder::Set2(_) => ScancodeSetKind::Set2,
+        }
+    }
+
+    /// Feed a scancode to the decoder, returning a key event once the
+    /// scancode completes one.
+    fn add_byte(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let result = match self {
+            Decoder::Set1(keyboard) => keyboard.add_byte(scancode),
+            Decoder::Set2(keyboard) => keyboard.add_byte(scancode),
+        };
+        result.ok().flatten()
+    }
+
+    /// Translate a key event into a key press using the current layout.
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        match self {
+            Decoder::Set1(keyboard) => keyboard.process_keyevent(event),
+            Decoder::Set2(keyboard) => keyboard.process_keyevent(event),
+        }
+    }
+
+    /// Feed a scancode to the decoder and track the modifier keys, see
+    /// `decode`.
+    fn decode(&mut self, scancode: u8) -> Option<DecodedKey> {
+        let event = self.add_byte(scancode)?;
+        if update_modifiers(&event) {
+            LEDS_DIRTY.store(true, Ordering::SeqCst);
+        }
+        if handle_console_keys(&event) {
+            return None;
+        }
+        self.process_keyevent(event)
+    }
+}
+
+lazy_static! {
+    /// Decoder turning scancodes into key presses. Only used outside of
+    /// interrupt context.
+    static ref DECODER: Mutex<Decoder> = Mutex::new(Decoder::new(ScancodeSetKind::Set1));
+}
+
+
+/// Register the keyboard interrupt handler and apply the `keymap=` and
+/// `scancodes=` options from the kernel command line.
+pub fn init() {
+    irq::register_irq(InterruptIndex::Keyboard.as_irq(), handle_interrupt)
+        .expect("Keyboard IRQ registration failed");
+
+    if let Some(name) = cmdline::value("keymap") {
+        match Layout::from_name(name) {
+            Some(layout) => set_layout(layout),
+            None => warn!("Unknown keymap '{}', using '{}'", name, layout().name()),
+        }
+    }
+    if cmdline::value("scancodes") == Some("2") {
+        if let Err(err) = set_scancode_set(ScancodeSetKind::Set2) {
+            warn!("Could not switch to scancode set 2: {:?}", err);
+        }
+    }
+    update_leds();
+}
+
+/// IRQ handler for the keyboard. Only queues the raw scancode, so that
+/// decoding and any output happen outside of interrupt context.
+fn handle_interrupt(_irq: u8) {
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    if scancode == RESPONSE_ACK || scancode == RESPONSE_RESEND {
+        // a reply to a command, not a key
+        return;
+    }
+    // a full queue counts the dropped scancode itself
+    if SCANCODES.push(scancode).is_ok() {
+        // whichever console reader runs first decodes the scancode for the
+        // active console, so wake them all
+        for waker in KEY_WAKERS.iter() {
+            waker.wake();
+        }
+    }
+}
+
+/// Feed a raw scancode to the decoder, returning a key press once the
+/// scancode completes one. Ctrl+letter combinations are returned as the
+/// corresponding control characters (e.g. Ctrl+C as `'\u{3}'`).
+/// The keys handled by `handle_console_keys` are not returned.
+pub fn decode(scancode: u8) -> Option<DecodedKey> {
+    let key = DECODER.lock().decode(scancode);
+    // the LED command waits for the keyboard's replies, so it is sent
+    // without holding the decoder lock
+    if LEDS_DIRTY.swap(false, Ordering::SeqCst) {
+        update_leds();
+    }
+    key
+}
+
+/// Handle the keys that control the screen: Shift+PageUp and
+/// Shift+PageDown scroll through the scrollback, by half a screen at a
+/// time, and Alt+F1 to Alt+F6 switch virtual consoles. Returns whether
+/// the event was one of these keys.
+fn handle_console_keys(event: &KeyEvent) -> bool {
+    let modifiers = modifiers();
+    let pressed = event.state == KeyState::Down;
+    if modifiers.shift() {
+        let lines = vga_buffer::BUFFER_HEIGHT / 2;
+        match event.code {
+            KeyCode::PageUp if pressed => vga_buffer::scroll_back(lines),
+            KeyCode::PageDown if pressed => vga_buffer::scroll_forward(lines),
+            KeyCode::PageUp | KeyCode::PageDown => {}
+            _ => return false,
+        }
+        return true;
+    }
+    if modifiers.alt() {
+        let console = match event.code {
+            KeyCode::F1 => 0,
+            KeyCode::F2 => 1,
+            KeyCode::F3 => 2,
+            KeyCode::F4 => 3,
+            KeyCode::F5 => 4,
+            KeyCode::F6 => 5,
+            _ => return false,
+        };
+        if pressed {
+            vga_buffer::switch_console(console);
+        }
+        return true;
+    }
+    false
+}
+
+/// Returns the next key press for virtual console `console`, if one is
+/// available, without blocking. Key presses go to the console that is on
+/// the screen when they are decoded.
+pub fn pop_key(console: usize) -> Option<DecodedKey> {
+    dispatch_keys();
+    KEYS.lock()[console].pop()
+}
+
+/// Register a waker to be woken when a key press may be available for
+/// virtual console `console`.
+pub fn register_key_waker(console: usize, waker: &Waker) {
+    KEY_WAKERS[console].register(waker);
+}
+
+/// Decode the queued scancodes, handing each key press to the active
+/// console.
+fn dispatch_keys() {
+    while let Some(scancode) = SCANCODES.pop() {
+        if let Some(key) = decode(scancode) {
+            let console = vga_buffer::active_console();
+            KEYS.lock()[console].push(key);
+            KEY_WAKERS[console].wake();
+        }
+    }
+}
+
+/// Returns the next key press for the active console, if one is
+/// available, without blocking.
+pub fn try_read_key() -> Option<DecodedKey> {
+    pop_key(vga_buffer::active_console())
+}
+
+/// Waits for the next key press for the active console and returns it,
+/// halting the CPU while no input is available. Must be called with
+/// interrupts enabled.
+pub fn read_key() -> DecodedKey {
+    loop {
+        if let Some(key) = try_read_key() {
+            return key;
+        }
+
+        // check for new input with interrupts disabled, so that a scancode
+        // arriving between the check and the `hlt` cannot be missed
+        interrupts::disable();
+        if SCANCODES.is_empty() {
+            interrupts::enable_interrupts_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Returns the number of scancodes dropped because they arrived faster
+/// than they were read.
+pub fn overflow_count() -> u64 {
+    SCANCODES.dropped()
+}
+
+
+// Layouts and scancode sets ---------------------------------------------------
+
+/// Returns the layout used to decode key presses.
+pub fn layout() -> Layout {
+    layouts::current()
+}
+
+/// Change the layout used to decode key presses.
+pub fn set_layout(layout: Layout) {
+    layouts::set(layout);
+}
+
+/// Returns the scancode set the keyboard is decoded with.
+pub fn scancode_set() -> ScancodeSetKind {
+    DECODER.lock().set()
+}
+
+/// Switch the keyboard to the given scancode set. The keyboard itself is
+/// always put in set 2; for set 1 the PS/2 controller translates it.
+pub fn set_scancode_set(set: ScancodeSetKind) -> Result<(), ps2::Ps2Error> {
+    ps2::send_command(Ps2Port::First, COMMAND_SCANCODE_SET, &[2], &mut [])?;
+    ps2::set_translation(set == ScancodeSetKind::Set1)?;
+    *DECODER.lock() = Decoder::new(set);
+    Ok(())
+}
+
+
+// Modifiers -------------------------------------------------------------------
+
+/// A snapshot of the state of the modifier and lock keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    /// The right Alt key, which acts as AltGr on many layouts.
+    pub right_alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    /// Returns whether either Shift key is held.
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    /// Returns whether either Ctrl key is held.
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// Returns whether either Alt key is held.
+    pub fn alt(&self) -> bool {
+        self.left_alt || self.right_alt
+    }
+
+    /// Returns whether letters should be upper case.
+    pub fn is_caps(&self) -> bool {
+        self.shift() ^ self.caps_lock
+    }
+
+    /// Converts a combination of the `MODIFIERS` bits.
+    fn from_bits(bits: u16) -> Modifiers {
+        Modifiers {
+            left_shift: bits & LEFT_SHIFT != 0,
+            right_shift: bits & RIGHT_SHIFT != 0,
+            left_ctrl: bits & LEFT_CTRL != 0,
+            right_ctrl: bits & RIGHT_CTRL != 0,
+            left_alt: bits & LEFT_ALT != 0,
+            right_alt: bits & RIGHT_ALT != 0,
+            caps_lock: bits & CAPS_LOCK != 0,
+            num_lock: bits & NUM_LOCK != 0,
+            scroll_lock: bits & SCROLL_LOCK != 0,
+        }
+    }
+}
+
+/// Returns the current state of the modifier and lock keys.
+pub fn modifiers() -> Modifiers {
+    Modifiers::from_bits(MODIFIERS.load(Ordering::Relaxed))
+}
+
+/// Track the modifier and lock keys. Returns whether a lock key was
+/// toggled, in which case the LEDs need updating.
+fn update_modifiers(event: &KeyEvent) -> bool {
+    let (bit, is_lock) = match event.code {
+        KeyCode::ShiftLeft => (LEFT_SHIFT, false),
+        KeyCode::ShiftRight => (RIGHT_SHIFT, false),
+        KeyCode::ControlLeft => (LEFT_CTRL, false),
+        KeyCode::ControlRight => (RIGHT_CTRL, false),
+        KeyCode::AltLeft => (LEFT_ALT, false),
+        KeyCode::AltRight => (RIGHT_ALT, false),
+        KeyCode::CapsLock => (CAPS_LOCK, true),
+        KeyCode::NumpadLock => (NUM_LOCK, true),
+        KeyCode::ScrollLock => (SCROLL_LOCK, true),
+        _ => return false,
+    };
+
+    match (event.state, is_lock) {
+        (KeyState::Down, true) => {
+            MODIFIERS.fetch_xor(bit, Ordering::Relaxed);
+            true
+        }
+        (KeyState::Down, false) => {
+            MODIFIERS.fetch_or(bit, Ordering::Relaxed);
+            false
+        }
+        (KeyState::Up, false) => {
+            MODIFIERS.fetch_and(!bit, Ordering::Relaxed);
+            false
+        }
+        (KeyState::Up, true) => false,
+    }
+}
+
+/// Set the keyboard LEDs to match the state of the lock keys.
+fn update_leds() {
+    let state = modifiers();
+    let leds = (state.scroll_lock as u8)
+        | (state.num_lock as u8) << 1
+        | (state.caps_lock as u8) << 2;
+    // the LEDs are cosmetic, so a keyboard that does not take the command
+    // is not an error worth reporting
+    let _ = ps2::send_command(Ps2Port::First, COMMAND_SET_LEDS, &[leds], &mut []);
+}
+
+
+// Key queues ------------------------------------------------------------------
+
+/// Key presses waiting to be read by the program on one virtual console.
+#[derive(Clone, Copy)]
+struct KeyQueue {
+    keys: [DecodedKey; KEY_QUEUE_SIZE],
+    /// Index of the oldest key press.
+    head: usize,
+    len: usize,
+}
+
+impl KeyQueue {
+    const EMPTY: KeyQueue = KeyQueue {
+        keys: [DecodedKey::Unicode('\0'); KEY_QUEUE_SIZE],
+        head: 0,
+        len: 0,
+    };
+
+    /// Add a key press. If the queue is full it is dropped, since nothing
+    /// is reading the console's input.
+    fn push(&mut self, key: DecodedKey) {
+        if self.len < KEY_QUEUE_SIZE {
+            self.keys[(self.head + self.len) % KEY_QUEUE_SIZE] = key;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<DecodedKey> {
+        if self.len == 0 {
+            return None;
+        }
+        let key = self.keys[self.head];
+        self.head = (self.head + 1) % KEY_QUEUE_SIZE;
+        self.len -= 1;
+        Some(key)
+    }
+}
@@ -0,0 +1,147 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyboardLayout};
+
+/// The keyboard layouts that can be selected at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    /// US English, 104 keys.
+    Us104,
+    /// UK English, 105 keys.
+    Uk105,
+    /// German QWERTZ, 105 keys.
+    De105,
+    /// US Dvorak, 104 keys.
+    Dvorak104,
+    /// French AZERTY.
+    Azerty,
+}
+
+impl Layout {
+    /// All available layouts.
+    pub const ALL: [Layout; 5] = [
+        Layout::Us104, Layout::Uk105, Layout::De105, Layout::Dvorak104, Layout::Azerty,
+    ];
+
+    /// Look up a layout by its short name, as used on the kernel command
+    /// line (e.g. `keymap=de`).
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "us" => Some(Layout::Us104),
+            "uk" | "gb" => Some(Layout::Uk105),
+            "de" => Some(Layout::De105),
+            "dvorak" => Some(Layout::Dvorak104),
+            "azerty" | "fr" => Some(Layout::Azerty),
+            _ => None,
+        }
+    }
+
+    /// Returns the short name of the layout.
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us104 => "us",
+            Layout::Uk105 => "uk",
+            Layout::De105 => "de",
+            Layout::Dvorak104 => "dvorak",
+            Layout::Azerty => "azerty",
+        }
+    }
+}
+
+/// The currently selected layout, as a `Layout`.
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us104 as u8);
+
+/// Returns the currently selected layout.
+pub fn current() -> Layout {
+    let index = LAYOUT.load(Ordering::Relaxed) as usize;
+    Layout::ALL.get(index).copied().unwrap_or(Layout::Us104)
+}
+
+/// Select the layout used to decode all following key presses.
+pub fn set(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+
+/// A layout that forwards to whichever `Layout` is currently selected.
+/// The `pc_keyboard` decoder is generic over its layout, so this lets the
+/// layout change without replacing the decoder.
+pub(super) struct DynamicLayout;
+
+impl KeyboardLayout for DynamicLayout {
+    fn map_keycode(
+        keycode: KeyCode,
+        modifiers: &pc_keyboard::Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match current() {
+            Layout::Us104 => layouts::Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Uk105 => layouts::Uk105Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::De105 => De105Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Dvorak104 =>
+                layouts::Dvorak104Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Azerty => layouts::Azerty::map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+
+/// German QWERTZ layout. Keys that are the same as on a US keyboard are
+/// forwarded to `Us104Key`.
+struct De105Key;
+
+impl KeyboardLayout for De105Key {
+    fn map_keycode(
+        keycode: KeyCode,
+        modifiers: &pc_keyboard::Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        // AltGr is not tracked by the decoder, so use our own modifiers
+        let state = super::modifiers();
+        let map_ctrl = handle_ctrl == HandleControl::MapLettersToUnicode;
+
+        let letter = |lower: char, upper: char| {
+            if map_ctrl && state.ctrl() && lower.is_ascii_lowercase() {
+                DecodedKey::Unicode((lower as u8 - b'a' + 1) as char)
+            } else if state.is_caps() {
+                DecodedKey::Unicode(upper)
+            } else {
+                DecodedKey::Unicode(lower)
+            }
+        };
+        let symbol = |normal: char, shifted: char, alt_gr: Option<char>| {
+            match alt_gr {
+                Some(character) if state.right_alt => DecodedKey::Unicode(character),
+                _ if state.shift() => DecodedKey::Unicode(shifted),
+                _ => DecodedKey::Unicode(normal),
+            }
+        };
+
+        match keycode {
+            KeyCode::BackTick => symbol('^', '°', None),
+            KeyCode::Key2 => symbol('2', '"', Some('²')),
+            KeyCode::Key3 => symbol('3', '§', Some('³')),
+            KeyCode::Key6 => symbol('6', '&', None),
+            KeyCode::Key7 => symbol('7', '/', Some('{')),
+            KeyCode::Key8 => symbol('8', '(', Some('[')),
+            KeyCode::Key9 => symbol('9', ')', Some(']')),
+            KeyCode::Key0 => symbol('0', '=', Some('}')),
+            KeyCode::Minus => symbol('ß', '?', Some('\\')),
+            KeyCode::Equals => symbol('´', '`', None),
+            KeyCode::Q if state.right_alt => DecodedKey::Unicode('@'),
+            KeyCode::E if state.right_alt => DecodedKey::Unicode('€'),
+            KeyCode::Y => letter('z', 'Z'),
+            KeyCode::Z => letter('y', 'Y'),
+            KeyCode::BracketSquareLeft => letter('ü', 'Ü'),
+            KeyCode::BracketSquareRight => symbol('+', '*', Some('~')),
+            KeyCode::SemiColon => letter('ö', 'Ö'),
+            KeyCode::Quote => letter('ä', 'Ä'),
+            KeyCode::BackSlash => symbol('#', '\'', None),
+            KeyCode::Comma => symbol(',', ';', None),
+            KeyCode::Fullstop => symbol('.', ':', None),
+            KeyCode::Slash => symbol('-', '_', None),
+            _ => layouts::Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
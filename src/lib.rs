@@ -1,6 +1,6 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
-#![feature(abi_x86_interrupt, alloc_error_handler, const_fn, const_in_array_repeat_expressions, custom_test_frameworks)]
+#![feature(abi_x86_interrupt, alloc_error_handler, const_fn, const_in_array_repeat_expressions, custom_test_frameworks, global_asm, llvm_asm, panic_info_message, try_reserve, wake_trait)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
@@ -14,10 +14,28 @@ use bootloader::{BootInfo, entry_point};
 use x86_64::instructions::port::Port;
 
 pub mod allocator;
+pub mod apic;
+pub mod cmdline;
+pub mod crash;
+pub mod emergency;
+pub mod framebuffer;
+pub mod gdb;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+pub mod log;
 pub mod memory;
+pub mod mouse;
+pub mod pci;
+pub mod pit;
+pub mod power;
+pub mod ps2;
+pub mod queue;
+pub mod rtc;
 pub mod serial;
+pub mod shell;
+pub mod task;
+pub mod time;
 pub mod vga_buffer;
 
 
@@ -72,8 +90,11 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 
 /// Logic for handling panics during tests.
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
-    serial_println!("[failed]\n");
-    serial_println!("Error: {}\n", info);
+    // a panic while printing the message would otherwise recurse forever
+    if !emergency::enter() {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+    }
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
@@ -88,8 +109,23 @@ entry_point!(test_kernel_main);
 
 /// Entry point for `cargo test`
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
     init();
+
+    // some tests need the heap and the APIC
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("Heap initialization failed");
+    vga_buffer::init_scrollback();
+    apic::init(&mut mapper, &mut frame_allocator)
+        .expect("APIC initialization failed");
+
     test_main();
     hlt_loop();
 }
@@ -105,8 +141,19 @@ fn panic(info: &PanicInfo) -> ! {
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::init_pics();
+    serial::init();
+    log::init();
+    time::init();
+    if let Err(err) = ps2::init() {
+        error!("PS/2 controller initialization failed: {:?}", err);
+    }
+    keyboard::init();
+    if let Err(err) = mouse::init() {
+        error!("Mouse initialization failed: {:?}", err);
+    }
     x86_64::instructions::interrupts::enable();
+    gdb::init();
 }
 
 /// Sends the `hlt` instruction to the CPU so that we can wait for
@@ -0,0 +1,385 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub mod ring;
+mod sinks;
+
+pub use ring::RingSink;
+pub use sinks::{SerialSink, VgaSink};
+
+use crate::cmdline;
+use crate::emergency;
+use crate::time;
+
+/// Maximum number of sinks that can be added with `add_sink`.
+const MAX_SINKS: usize = 8;
+/// Maximum number of targets that can have their own level.
+const MAX_FILTERS: usize = 16;
+/// Longest target that can have its own level.
+const MAX_TARGET_LEN: usize = 32;
+/// Value of a level filter that lets nothing through.
+const OFF: u8 = 0;
+
+/// How important a log message is. Messages less important than the
+/// level set for their target are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// All levels, from most to least important.
+    pub const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    /// Returns the level with the given name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL.iter().copied().find(|level| name.eq_ignore_ascii_case(level.name()))
+    }
+
+    /// Returns the lowercase name of the level, as accepted by `from_name`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// Parses a level filter: a level name, or "off" for `None`.
+    pub fn parse_filter(name: &str) -> Option<Option<Level>> {
+        if name.eq_ignore_ascii_case("off") {
+            Some(None)
+        } else {
+            Level::from_name(name).map(Some)
+        }
+    }
+}
+
+/// Errors that can occur when configuring the logger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    /// All slots for sinks or target levels are in use.
+    NoFreeSlot,
+    /// The target is longer than `MAX_TARGET_LEN`.
+    TargetTooLong,
+}
+
+/// One log message.
+pub struct Record<'a> {
+    pub level: Level,
+    /// The module the message comes from, without the crate name, e.g.
+    /// "allocator::fixed_size_block".
+    pub target: &'a str,
+    /// Timer ticks since boot when the message was logged.
+    pub ticks: u64,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for Record<'_> {
+    /// Formats the record as "[seconds.hundredths] LEVEL target: message".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:<5} {}: {}", Timestamp(self.ticks), self.level.name(), self.target, self.args)
+    }
+}
+
+/// Timer ticks since boot, formatted as "[seconds.hundredths]".
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp(pub u64);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hz = time::TIMER_HZ as u64;
+        write!(f, "[{:>5}.{:02}]", self.0 / hz, self.0 % hz * 100 / hz)
+    }
+}
+
+/// Somewhere log messages are written to.
+pub trait Sink: Sync {
+    fn write(&self, record: &Record);
+}
+
+/// The target and level of a target filter.
+#[derive(Clone, Copy)]
+struct Filter {
+    target: [u8; MAX_TARGET_LEN],
+    len: usize,
+    level: u8,
+}
+
+impl Filter {
+    fn target(&self) -> &str {
+        core::str::from_utf8(&self.target[..self.len]).unwrap_or("")
+    }
+
+    /// Returns whether the filter applies to `target`: the target is the
+    /// filter's module or one inside it.
+    fn matches(&self, target: &str) -> bool {
+        let filter = self.target();
+        target.starts_with(filter)
+            && (target.len() == filter.len() || target[filter.len()..].starts_with("::"))
+    }
+}
+
+/// Level of targets without a filter of their own.
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// Least important level any target lets through, so that most disabled
+/// messages are dropped without taking a lock.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+/// Levels of targets set with `set_target_level`.
+static FILTERS: Mutex<[Option<Filter>; MAX_FILTERS]> = Mutex::new([None; MAX_FILTERS]);
+/// Where log messages are written.
+static SINKS: Mutex<[Option<&'static dyn Sink>; MAX_SINKS]> = Mutex::new([
+    Some(&VgaSink), Some(&SerialSink), Some(&RingSink), None, None, None, None, None,
+]);
+
+
+/// Log a message at the given level, e.g. `log!(Level::Info, "{} MiB", mb)`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::log::may_log(level) {
+            $crate::log::_log(level, module_path!(), format_args!($($arg)*));
+        }
+    }};
+}
+
+/// Log a message at the error level.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+/// Log a message at the warning level.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+/// Log a message at the info level.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+/// Log a message at the debug level.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+/// Log a message at the trace level.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+/// Returns whether any target lets messages at `level` through. Used by
+/// `log!` to skip formatting messages that would be dropped.
+#[doc(hidden)]
+pub fn may_log(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &str, args: fmt::Arguments) {
+    // strip the crate name
+    let target = module_path.splitn(2, "::").nth(1).unwrap_or(module_path);
+    interrupts::without_interrupts(|| {
+        // messages are logged with interrupts disabled, so the filters and
+        // sinks can only be locked here if an exception handler logs while
+        // they are in use; drop the message rather than deadlock
+        let filter = match level_filter(target) {
+            Some(filter) => filter,
+            None => return ring::count_dropped(),
+        };
+        if level as u8 > filter {
+            return;
+        }
+        let record = Record { level, target, ticks: time::ticks(), args };
+        match emergency::try_lock(&SINKS) {
+            Some(sinks) => {
+                for sink in sinks.iter().flatten() {
+                    sink.write(&record);
+                }
+            }
+            None => ring::count_dropped(),
+        }
+    });
+}
+
+/// Returns the least important level let through for `target`, as a
+/// `Level` value or `OFF`. The longest matching filter wins. Returns
+/// `None` if the filters are in use.
+fn level_filter(target: &str) -> Option<u8> {
+    let filters = emergency::try_lock(&FILTERS)?;
+    Some(filters.iter().flatten()
+        .filter(|filter| filter.matches(target))
+        .max_by_key(|filter| filter.len)
+        .map_or(DEFAULT_LEVEL.load(Ordering::Relaxed), |filter| filter.level))
+}
+
+/// Recompute `MAX_LEVEL` from the default level and the filters.
+fn update_max_level(filters: &[Option<Filter>]) {
+    let max = filters.iter().flatten()
+        .map(|filter| filter.level)
+        .fold(DEFAULT_LEVEL.load(Ordering::Relaxed), u8::max);
+    MAX_LEVEL.store(max, Ordering::Relaxed);
+}
+
+
+// Configuration ---------------------------------------------------------------
+
+/// Apply the `loglevel=` option from the kernel command line: a comma
+/// separated list of a default level and `target=level` pairs, e.g.
+/// `loglevel=warn,allocator=trace`. Levels are "off", "error", "warn",
+/// "info", "debug" and "trace".
+pub fn init() {
+    let option = match cmdline::value("loglevel") {
+        Some(option) => option,
+        None => return,
+    };
+    for item in option.split(',').filter(|item| !item.is_empty()) {
+        let mut parts = item.splitn(2, '=');
+        let result = match (parts.next(), parts.next()) {
+            (Some(level), None) => Level::parse_filter(level).map(|level| {
+                set_level(level);
+                Ok(())
+            }),
+            (Some(target), Some(level)) => Level::parse_filter(level)
+                .map(|level| set_target_level(target, level)),
+            _ => None,
+        };
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(err)) => warn!("Could not set log level '{}': {:?}", item, err),
+            None => warn!("Invalid log level '{}'", item),
+        }
+    }
+}
+
+/// Set the level of targets without a level of their own, or turn their
+/// messages off with `None`.
+pub fn set_level(level: Option<Level>) {
+    interrupts::without_interrupts(|| {
+        let filters = FILTERS.lock();
+        DEFAULT_LEVEL.store(level.map_or(OFF, |level| level as u8), Ordering::Relaxed);
+        update_max_level(&*filters);
+    });
+}
+
+/// Returns the level of targets without a level of their own.
+pub fn level() -> Option<Level> {
+    let level = DEFAULT_LEVEL.load(Ordering::Relaxed);
+    Level::ALL.iter().copied().find(|&l| l as u8 == level)
+}
+
+/// Set the level of `target` and the modules inside it (e.g. "allocator"
+/// for "allocator::fixed_size_block"), or turn their messages off with
+/// `None`.
+pub fn set_target_level(target: &str, level: Option<Level>) -> Result<(), LogError> {
+    if target.len() > MAX_TARGET_LEN {
+        return Err(LogError::TargetTooLong);
+    }
+    let mut filter = Filter {
+        target: [0; MAX_TARGET_LEN],
+        len: target.len(),
+        level: level.map_or(OFF, |level| level as u8),
+    };
+    filter.target[..target.len()].copy_from_slice(target.as_bytes());
+
+    interrupts::without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+        let slot = match filters.iter().position(|slot| matches!(slot, Some(f) if f.target() == target)) {
+            Some(index) => &mut filters[index],
+            None => filters.iter_mut().find(|slot| slot.is_none()).ok_or(LogError::NoFreeSlot)?,
+        };
+        *slot = Some(filter);
+        update_max_level(&*filters);
+        Ok(())
+    })
+}
+
+/// Remove the level set for `target` with `set_target_level`, so that it
+/// uses the default level again. Returns whether it had a level.
+pub fn clear_target_level(target: &str) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+        match filters.iter_mut().find(|slot| matches!(slot, Some(f) if f.target() == target)) {
+            Some(slot) => {
+                *slot = None;
+                update_max_level(&*filters);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Add a sink that all log messages let through are written to.
+pub fn add_sink(sink: &'static dyn Sink) -> Result<(), LogError> {
+    interrupts::without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let free_slot = sinks.iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(LogError::NoFreeSlot)?;
+        *free_slot = Some(sink);
+        Ok(())
+    })
+}
+
+/// Stop writing log messages to `sink`, which may be one of the sinks
+/// there are by default. Returns whether it was a sink.
+pub fn remove_sink(sink: &'static dyn Sink) -> bool {
+    let address = sink as *const dyn Sink as *const u8;
+    interrupts::without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let slot = sinks.iter_mut()
+            .find(|slot| matches!(slot, Some(s) if *s as *const dyn Sink as *const u8 == address));
+        match slot {
+            Some(slot) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_target_levels() {
+    set_target_level("test_log", Some(Level::Trace)).expect("setting level failed");
+    set_target_level("test_log::quiet", None).expect("setting level failed");
+    assert!(may_log(Level::Trace));
+    assert_eq!(level_filter("test_log::module"), Some(Level::Trace as u8));
+    assert_eq!(level_filter("test_log::quiet::inner"), Some(OFF));
+    // only whole module names match
+    assert_eq!(level_filter("test_logger"), Some(level().map_or(OFF, |level| level as u8)));
+
+    assert!(clear_target_level("test_log"));
+    assert!(clear_target_level("test_log::quiet"));
+    assert_eq!(may_log(Level::Trace), level() == Some(Level::Trace));
+}
+
+#[test_case]
+fn test_log_while_locked() {
+    let before = ring::dropped();
+    interrupts::without_interrupts(|| {
+        let _sinks = SINKS.lock();
+        warn!("dropped rather than waiting for the sinks");
+    });
+    assert_eq!(ring::dropped(), before + 1);
+}
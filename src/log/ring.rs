@@ -0,0 +1,191 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{Level, Record, Sink, Timestamp};
+use crate::emergency;
+
+/// Number of log messages kept.
+const ENTRIES: usize = 128;
+/// Longest message kept, including its target; longer ones are cut off.
+const TEXT_LEN: usize = 120;
+
+/// A log message kept in memory.
+#[derive(Clone, Copy)]
+pub struct Entry {
+    /// Number of the message, counting from 0 at boot. Messages that could
+    /// not be recorded leave a gap.
+    pub seq: u64,
+    /// Timer ticks since boot when the message was logged.
+    pub ticks: u64,
+    pub level: Level,
+    text: [u8; TEXT_LEN],
+    len: usize,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry { seq: 0, ticks: 0, level: Level::Error, text: [0; TEXT_LEN], len: 0 };
+
+    /// Returns the target and the message, as "target: message".
+    pub fn text(&self) -> &str {
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Entry {
+    /// Append as much of `s` as fits, without splitting a character.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(TEXT_LEN - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Entry {
+    /// Formats the entry like the `Record` it was made from.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:<5} {}", Timestamp(self.ticks), self.level.name(), self.text())
+    }
+}
+
+/// The most recent log messages, oldest first starting at `start`.
+struct Ring {
+    entries: [Entry; ENTRIES],
+    start: usize,
+    len: usize,
+}
+
+impl Ring {
+    fn get(&self, i: usize) -> &Entry {
+        &self.entries[(self.start + i) % ENTRIES]
+    }
+
+    /// Add an entry, overwriting the oldest one if the ring is full.
+    fn push(&mut self, entry: Entry) {
+        if self.len < ENTRIES {
+            let index = (self.start + self.len) % ENTRIES;
+            self.entries[index] = entry;
+            self.len += 1;
+        } else {
+            self.entries[self.start] = entry;
+            self.start = (self.start + 1) % ENTRIES;
+        }
+    }
+
+    /// Returns the oldest entry numbered `seq` or later.
+    fn first_since(&self, seq: u64) -> Option<Entry> {
+        (0..self.len).map(|i| *self.get(i)).find(|entry| entry.seq >= seq)
+    }
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring { entries: [Entry::EMPTY; ENTRIES], start: 0, len: 0 });
+/// Number of the next message logged.
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+/// Number of messages that could not be recorded.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps the most recent log messages in a static buffer, so that they can
+/// be read with `for_each_entry` even if they were never shown, e.g.
+/// because they were logged before the heap existed or while the screen
+/// was in a graphics mode.
+pub struct RingSink;
+
+impl Sink for RingSink {
+    fn write(&self, record: &Record) {
+        let mut entry = Entry {
+            seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+            ticks: record.ticks,
+            level: record.level,
+            ..Entry::EMPTY
+        };
+        let _ = write!(entry, "{}: {}", record.target, record.args);
+        // messages are logged with interrupts disabled, so the ring can only
+        // be locked here if an exception handler logs while the ring is in
+        // use; drop the message rather than deadlock
+        match RING.try_lock() {
+            Some(mut ring) => ring.push(entry),
+            None => count_dropped(),
+        }
+    }
+}
+
+
+/// Call `f` with each message kept numbered `since` or later, oldest first.
+/// Returns the number to pass as `since` to read only newer messages.
+///
+/// Messages are copied out one at a time, so `f` may log and take as long
+/// as it likes.
+pub fn for_each_entry(since: u64, mut f: impl FnMut(&Entry)) -> u64 {
+    let mut next = since;
+    while let Some(entry) = interrupts::without_interrupts(|| emergency::lock(&RING).first_since(next)) {
+        next = entry.seq + 1;
+        f(&entry);
+    }
+    next
+}
+
+/// Returns the number of the oldest of the last `count` messages kept, to
+/// pass to `for_each_entry`.
+pub fn tail_seq(count: usize) -> u64 {
+    interrupts::without_interrupts(|| {
+        let ring = RING.lock();
+        if count == 0 || ring.len == 0 {
+            NEXT_SEQ.load(Ordering::Relaxed)
+        } else {
+            ring.get(ring.len - count.min(ring.len)).seq
+        }
+    })
+}
+
+/// Forget the messages numbered before `before`.
+pub fn clear(before: u64) {
+    interrupts::without_interrupts(|| {
+        let mut ring = RING.lock();
+        while ring.len > 0 && ring.get(0).seq < before {
+            ring.start = (ring.start + 1) % ENTRIES;
+            ring.len -= 1;
+        }
+    });
+}
+
+/// Record that a message was dropped because the logger was in use.
+pub(super) fn count_dropped() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of messages that could not be recorded because the
+/// logger was in use.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_ring() {
+    let since = tail_seq(0);
+    for i in 0..2 {
+        RingSink.write(&Record { level: Level::Debug, target: "test_ring", ticks: 0, args: format_args!("message {}", i) });
+    }
+
+    let mut count = 0;
+    let next = for_each_entry(since, |entry| {
+        assert_eq!(entry.seq, since + count);
+        assert_eq!(entry.level, Level::Debug);
+        assert!(entry.text().starts_with("test_ring: message"));
+        count += 1;
+    });
+    assert_eq!(count, 2);
+    assert_eq!(next, since + 2);
+    assert_eq!(tail_seq(1), since + 1);
+
+    clear(next);
+    assert_eq!(for_each_entry(since, |_| panic!("entry not cleared")), since);
+}
@@ -0,0 +1,24 @@
+use super::{Record, Sink};
+use crate::serial::{self, Output};
+use crate::vga_buffer;
+
+/// Writes log messages to the screen, like `println!`. Messages logged
+/// while the screen is in use, e.g. by an exception handler, are dropped;
+/// they can still be read with `ring::for_each_entry`.
+pub struct VgaSink;
+
+impl Sink for VgaSink {
+    fn write(&self, record: &Record) {
+        vga_buffer::_try_print(format_args!("{}\n", record));
+    }
+}
+
+/// Writes log messages to the serial port of `Output::Log`. Like with
+/// `VgaSink`, messages logged while the port is in use are dropped.
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn write(&self, record: &Record) {
+        serial::try_print_to(Output::Log, format_args!("{}\n", record));
+    }
+}
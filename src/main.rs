@@ -13,8 +13,14 @@ use x86_64::VirtAddr;
 
 use rust_os::{
     allocator,
+    apic,
+    framebuffer,
     memory,
     println,
+    shell,
+    task::{executor::Executor, Task},
+    time::{self, tsc},
+    vga_buffer,
 };
 
 // This macro adds a _start() function (which replaces the typical
@@ -36,6 +42,20 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     };
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("Heap initialization failed");
+    vga_buffer::init_scrollback();
+
+    // set up the local APIC and calibrate its timer
+    apic::init(&mut mapper, &mut frame_allocator)
+        .expect("APIC initialization failed");
+    println!("APIC timer: {} ticks/ms", apic::timer_ticks_per_ms());
+    println!("TSC: {} Hz ({:?}, invariant: {})",
+        tsc::frequency(), tsc::frequency_source(), tsc::is_invariant());
+    println!("Current time: {}", time::now());
+
+    // switch to a graphics mode if the command line asks for one
+    if let Err(err) = framebuffer::init(&mut mapper, &mut frame_allocator) {
+        println!("Could not switch to graphics mode: {:?}", err);
+    }
 
     // allocate a number on the heap
     let heap_value = Box::new(41);
@@ -60,7 +80,10 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     test_main();
 
     println!("It did not crash!");
-    rust_os::hlt_loop();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(shell::run()));
+    executor.run();
 }
 
 
@@ -68,8 +91,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    rust_os::hlt_loop();
+    rust_os::crash::report(info)
 }
 
 /// This function is called on panic (during tests).
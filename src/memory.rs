@@ -1,17 +1,32 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use x86_64::{
     registers::control::Cr3,
     PhysAddr,
     structures::paging::{
+        mapper::MapToError,
         FrameAllocator,
+        Mapper,
         OffsetPageTable,
+        Page,
         PageTable,
+        PageTableFlags,
         PhysFrame,
         Size4KiB,
     },
     VirtAddr,
 };
 
+/// Start of the virtual address region used for memory-mapped I/O.
+pub const MMIO_START: u64 = 0x_5555_5555_0000;
+
+/// Next free virtual address in the memory-mapped I/O region.
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+/// Virtual address the complete physical memory is mapped at, or 0 before
+/// `init` is called.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -19,6 +34,7 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must only be called once
 /// to avoid aliasing `&mut` references (which is undefined behaviour).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
@@ -41,6 +57,44 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     &mut *page_table_ptr  // unsafe
 }
 
+/// Returns the physical address `addr` is mapped to, found by walking the
+/// active page tables, or `None` if it is not mapped or `init` has not
+/// been called yet. Unlike `OffsetPageTable`, this needs no `&mut` access
+/// to the page tables, so it can be used anywhere, e.g. by a debugger.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return None;
+    }
+    let (level_4_table_frame, _) = Cr3::read();
+    let mut frame = level_4_table_frame.start_address();
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    for (level, &index) in indexes.iter().enumerate() {
+        let table = unsafe { &*((offset + frame.as_u64()) as *const PageTable) };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        // a huge page in a level 3 (1 GiB) or level 2 (2 MiB) table
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let page_size = 1u64 << (12 + 9 * (3 - level));
+            return Some(entry.addr() + (addr.as_u64() & (page_size - 1)));
+        }
+        frame = entry.addr();
+    }
+    Some(frame + (addr.as_u64() & 0xfff))
+}
+
+/// Returns the address physical address `addr` can be accessed at through
+/// the mapping of the complete physical memory, or `None` if `init` has
+/// not been called yet.
+pub fn physical_to_virtual(addr: PhysAddr) -> Option<VirtAddr> {
+    match PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) {
+        0 => None,
+        offset => Some(VirtAddr::new(offset + addr.as_u64())),
+    }
+}
+
 /// A FrameAllocator that returns usable frames from the
 /// bootloader's memory map.
 pub struct BootInfoFrameAllocator {
@@ -85,4 +139,39 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
         self.next += 1;
         frame
     }
-}
\ No newline at end of file
+}
+
+/// Map `size` bytes of device memory starting at physical address `phys`
+/// into the memory-mapped I/O region, with caching disabled. Returns the
+/// virtual address corresponding to `phys`.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// given physical range belongs to a device rather than to RAM that may
+/// be handed out by the frame allocator.
+pub unsafe fn map_mmio(
+    phys: PhysAddr,
+    size: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first_frame: PhysFrame = PhysFrame::containing_address(phys);
+    let last_frame: PhysFrame = PhysFrame::containing_address(phys + (size as u64 - 1));
+    let frame_range = PhysFrame::range_inclusive(first_frame, last_frame);
+
+    // reserve a block of virtual pages large enough for the whole range
+    let num_pages = last_frame.start_address().as_u64()
+        - first_frame.start_address().as_u64() + 4096;
+    let virt_start = VirtAddr::new(NEXT_MMIO.fetch_add(num_pages, Ordering::SeqCst));
+    let first_page: Page = Page::containing_address(virt_start);
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+    for (i, frame) in frame_range.enumerate() {
+        let page = first_page + i as u64;
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
+
+    Ok(virt_start + (phys.as_u64() - first_frame.start_address().as_u64()))
+}
@@ -0,0 +1,383 @@
+use core::task::Waker;
+
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::interrupts::{self as irq, InterruptIndex};
+use crate::ps2::{self, DeviceType, Ps2Error, Ps2Port};
+use crate::queue::ByteQueue;
+use crate::vga_buffer::{BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// Mouse command: send the device ID.
+const COMMAND_IDENTIFY: u8 = 0xf2;
+/// Mouse command: set the sample rate to the value in the next byte.
+const COMMAND_SET_SAMPLE_RATE: u8 = 0xf3;
+/// Mouse command: start sending movement packets.
+const COMMAND_ENABLE_REPORTING: u8 = 0xf4;
+/// Mouse command: stop sending movement packets.
+const COMMAND_DISABLE_REPORTING: u8 = 0xf5;
+
+/// Samples per second once initialization is done.
+const SAMPLE_RATE: u8 = 100;
+
+// Bits of the first byte of a packet.
+const PACKET_BUTTONS: u8 = 0b111;
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+/// Size in pixels of a character cell in VGA text mode, used to map the
+/// pointer position to a text cell.
+const CHAR_WIDTH: i32 = 8;
+const CHAR_HEIGHT: i32 = 16;
+
+/// The most events a single packet can produce: one move, a press or
+/// release for each of the five buttons and one scroll.
+const MAX_EVENTS_PER_PACKET: usize = 7;
+
+/// Raw bytes received from the mouse, queued by the interrupt handler.
+static BYTES: ByteQueue = ByteQueue::new();
+/// Waker of the task waiting for mouse input, if any.
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Packet decoder and pointer state. Only used outside of interrupt
+/// context.
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+
+/// The buttons a PS/2 mouse can report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    /// The first side button of a 5-button mouse ("back").
+    Fourth,
+    /// The second side button of a 5-button mouse ("forward").
+    Fifth,
+}
+
+impl MouseButton {
+    /// All buttons, in the order their events are reported.
+    pub const ALL: [MouseButton; 5] = [
+        MouseButton::Left,
+        MouseButton::Right,
+        MouseButton::Middle,
+        MouseButton::Fourth,
+        MouseButton::Fifth,
+    ];
+
+    /// Returns the bit of the button in the button state.
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Something the mouse did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEvent {
+    /// The pointer moved to (`x`, `y`), by (`dx`, `dy`) pixels. Positive
+    /// `dy` is downwards, like screen coordinates.
+    Move { x: i32, y: i32, dx: i32, dy: i32 },
+    /// A button was pressed.
+    ButtonDown(MouseButton),
+    /// A button was released.
+    ButtonUp(MouseButton),
+    /// The wheel was turned; positive values scroll down.
+    Scroll(i8),
+}
+
+/// The contents of one movement packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Packet {
+    buttons: u8,
+    dx: i32,
+    /// Positive values are upwards, as sent by the mouse.
+    dy: i32,
+    scroll: i8,
+}
+
+impl Packet {
+    /// Parse a packet in the format used by `device`: 3 bytes for a
+    /// standard mouse, 4 for one with a scroll wheel.
+    fn parse(bytes: &[u8], device: DeviceType) -> Packet {
+        let flags = bytes[0];
+        let mut dx = bytes[1] as i32;
+        let mut dy = bytes[2] as i32;
+        if flags & PACKET_X_SIGN != 0 {
+            dx -= 0x100;
+        }
+        if flags & PACKET_Y_SIGN != 0 {
+            dy -= 0x100;
+        }
+        // an overflowed delta is garbage, so ignore it
+        if flags & PACKET_X_OVERFLOW != 0 {
+            dx = 0;
+        }
+        if flags & PACKET_Y_OVERFLOW != 0 {
+            dy = 0;
+        }
+
+        let mut buttons = flags & PACKET_BUTTONS;
+        let scroll = match device {
+            DeviceType::ScrollMouse => bytes[3] as i8,
+            DeviceType::FiveButtonMouse => {
+                // bits 4 and 5 are the side buttons, the low nibble is
+                // the signed wheel movement
+                buttons |= (bytes[3] >> 1) & 0b1_1000;
+                ((bytes[3] << 4) as i8) >> 4
+            }
+            _ => 0,
+        };
+        Packet { buttons, dx, dy, scroll }
+    }
+}
+
+/// Assembles packets from the raw bytes and tracks the pointer.
+struct Mouse {
+    device: DeviceType,
+    packet: [u8; 4],
+    received: usize,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    buttons: u8,
+    /// Events produced by the last packet that have not been returned yet.
+    pending: [MouseEvent; MAX_EVENTS_PER_PACKET],
+    pending_len: usize,
+    pending_next: usize,
+}
+
+impl Mouse {
+    const fn new() -> Self {
+        let width = BUFFER_WIDTH as i32 * CHAR_WIDTH;
+        let height = BUFFER_HEIGHT as i32 * CHAR_HEIGHT;
+        Mouse {
+            device: DeviceType::StandardMouse,
+            packet: [0; 4],
+            received: 0,
+            x: width / 2,
+            y: height / 2,
+            width,
+            height,
+            buttons: 0,
+            pending: [MouseEvent::Scroll(0); MAX_EVENTS_PER_PACKET],
+            pending_len: 0,
+            pending_next: 0,
+        }
+    }
+
+    /// Returns the number of bytes in a packet from the current device.
+    fn packet_size(&self) -> usize {
+        match self.device {
+            DeviceType::ScrollMouse | DeviceType::FiveButtonMouse => 4,
+            _ => 3,
+        }
+    }
+
+    /// Add an event to the events of the current packet.
+    fn queue(&mut self, event: MouseEvent) {
+        self.pending[self.pending_len] = event;
+        self.pending_len += 1;
+    }
+
+    /// Update the pointer state from a packet and queue the resulting
+    /// events.
+    fn apply(&mut self, packet: Packet) {
+        self.pending_len = 0;
+        self.pending_next = 0;
+
+        let x = (self.x + packet.dx).max(0).min(self.width - 1);
+        let y = (self.y - packet.dy).max(0).min(self.height - 1);
+        // report the movement actually made, so a pointer pushed against
+        // the edge of the screen does not keep producing events
+        let (dx, dy) = (x - self.x, y - self.y);
+        if dx != 0 || dy != 0 {
+            self.x = x;
+            self.y = y;
+            self.queue(MouseEvent::Move { x, y, dx, dy });
+        }
+
+        let changed = self.buttons ^ packet.buttons;
+        for &button in MouseButton::ALL.iter() {
+            if changed & button.bit() == 0 {
+                continue;
+            }
+            if packet.buttons & button.bit() != 0 {
+                self.queue(MouseEvent::ButtonDown(button));
+            } else {
+                self.queue(MouseEvent::ButtonUp(button));
+            }
+        }
+        self.buttons = packet.buttons;
+
+        if packet.scroll != 0 {
+            self.queue(MouseEvent::Scroll(packet.scroll));
+        }
+    }
+
+    /// Return the next event, decoding queued bytes as needed.
+    fn next_event(&mut self) -> Option<MouseEvent> {
+        loop {
+            if self.pending_next < self.pending_len {
+                self.pending_next += 1;
+                return Some(self.pending[self.pending_next - 1]);
+            }
+
+            let byte = BYTES.pop()?;
+            if self.received == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+                // not the start of a packet, so we lost a byte somewhere;
+                // skip until we are back in sync
+                continue;
+            }
+            self.packet[self.received] = byte;
+            self.received += 1;
+            if self.received == self.packet_size() {
+                self.received = 0;
+                let packet = Packet::parse(&self.packet, self.device);
+                self.apply(packet);
+            }
+        }
+    }
+}
+
+
+/// Set up the mouse on the second PS/2 port, enabling the scroll wheel
+/// and extra buttons if it has them, and register its interrupt handler.
+/// `ps2::init` must have been called first.
+pub fn init() -> Result<(), Ps2Error> {
+    match ps2::device_type(Ps2Port::Second) {
+        Some(device) if device.is_mouse() => {}
+        _ => return Err(Ps2Error::NoDevice(Ps2Port::Second)),
+    }
+
+    send_command(COMMAND_DISABLE_REPORTING, &[])?;
+    // magic sequences of sample rates switch IntelliMouse-compatible mice
+    // into 4-byte packets with the wheel, and then to 5 buttons
+    let mut device = identify()?;
+    if device == DeviceType::StandardMouse {
+        set_sample_rates(&[200, 100, 80])?;
+        device = identify()?;
+    }
+    if device == DeviceType::ScrollMouse {
+        set_sample_rates(&[200, 200, 80])?;
+        device = identify()?;
+    }
+    set_sample_rates(&[SAMPLE_RATE])?;
+    MOUSE.lock().device = device;
+
+    irq::register_irq(InterruptIndex::Mouse.as_irq(), handle_interrupt)
+        .expect("Mouse IRQ registration failed");
+    send_command(COMMAND_ENABLE_REPORTING, &[])
+}
+
+/// Send a command to the mouse.
+fn send_command(command: u8, args: &[u8]) -> Result<(), Ps2Error> {
+    ps2::send_command(Ps2Port::Second, command, args, &mut [])
+}
+
+/// Ask the mouse what kind of mouse it is.
+fn identify() -> Result<DeviceType, Ps2Error> {
+    let mut id = [0];
+    ps2::send_command(Ps2Port::Second, COMMAND_IDENTIFY, &[], &mut id)?;
+    Ok(DeviceType::from_id(&id))
+}
+
+/// Set each of the given sample rates in turn.
+fn set_sample_rates(rates: &[u8]) -> Result<(), Ps2Error> {
+    for &rate in rates {
+        send_command(COMMAND_SET_SAMPLE_RATE, &[rate])?;
+    }
+    Ok(())
+}
+
+/// IRQ handler for the mouse. Only queues the raw byte, so that packets
+/// are assembled outside of interrupt context.
+fn handle_interrupt(_irq: u8) {
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    // a full queue counts the dropped byte itself
+    if BYTES.push(byte).is_ok() {
+        WAKER.wake();
+    }
+}
+
+/// Remove and return the next mouse event, if any.
+pub fn pop_event() -> Option<MouseEvent> {
+    MOUSE.lock().next_event()
+}
+
+/// Register a waker to be woken when the mouse sends data.
+pub fn register_waker(waker: &Waker) {
+    WAKER.register(waker);
+}
+
+/// Returns the number of mouse bytes dropped because the queue was full.
+/// Dropped bytes make the decoder skip packets until it is back in sync.
+pub fn overflow_count() -> u64 {
+    BYTES.dropped()
+}
+
+/// Returns the kind of mouse found, with its wheel and extra buttons
+/// enabled.
+pub fn device_type() -> DeviceType {
+    MOUSE.lock().device
+}
+
+/// Returns the pointer position in pixels.
+pub fn position() -> (i32, i32) {
+    let mouse = MOUSE.lock();
+    (mouse.x, mouse.y)
+}
+
+/// Returns the column and row of the text cell under the pointer.
+pub fn text_position() -> (usize, usize) {
+    let (x, y) = position();
+    ((x / CHAR_WIDTH) as usize, (y / CHAR_HEIGHT) as usize)
+}
+
+/// Returns whether `button` is currently held down.
+pub fn is_pressed(button: MouseButton) -> bool {
+    MOUSE.lock().buttons & button.bit() != 0
+}
+
+/// Set the size of the screen in pixels that the pointer is kept in. It
+/// defaults to the size of the VGA text screen with 8x16 pixel characters.
+pub fn set_bounds(width: i32, height: i32) {
+    let mut mouse = MOUSE.lock();
+    mouse.width = width.max(1);
+    mouse.height = height.max(1);
+    mouse.x = mouse.x.min(mouse.width - 1);
+    mouse.y = mouse.y.min(mouse.height - 1);
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_parse_packet() {
+    // left button, moved left by 2 and down by 3
+    let packet = Packet::parse(&[0b0011_1001, 0xfe, 0xfd], DeviceType::StandardMouse);
+    assert_eq!(packet, Packet { buttons: 0b001, dx: -2, dy: -3, scroll: 0 });
+
+    // fourth button and one notch of scrolling up
+    let packet = Packet::parse(&[0b0000_1000, 0, 0, 0b0001_1111],
+        DeviceType::FiveButtonMouse);
+    assert_eq!(packet, Packet { buttons: 0b1000, dx: 0, dy: 0, scroll: -1 });
+}
+
+#[test_case]
+fn test_pointer_clamped_to_screen() {
+    let mut mouse = Mouse::new();
+    mouse.apply(Packet { buttons: 0b010, dx: -10_000, dy: 10_000, scroll: 0 });
+    assert_eq!(mouse.next_event(),
+        Some(MouseEvent::Move { x: 0, y: 0, dx: -mouse.width / 2, dy: -mouse.height / 2 }));
+    assert_eq!(mouse.next_event(), Some(MouseEvent::ButtonDown(MouseButton::Right)));
+
+    // already in the corner, so only the release is reported
+    mouse.apply(Packet { buttons: 0, dx: -1, dy: 1, scroll: 0 });
+    assert_eq!(mouse.next_event(), Some(MouseEvent::ButtonUp(MouseButton::Right)));
+    assert_eq!((mouse.x, mouse.y), (0, 0));
+}
@@ -0,0 +1,111 @@
+use spin::Mutex;
+use x86_64::{instructions::interrupts, instructions::port::Port, PhysAddr};
+
+// Offsets of registers in the configuration space header.
+const REG_ID: u8 = 0x00;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0c;
+const REG_BAR0: u8 = 0x10;
+
+/// Vendor ID read from slots without a device.
+const NO_VENDOR: u16 = 0xffff;
+/// Header type flag: the device has functions other than 0.
+const MULTI_FUNCTION: u8 = 1 << 7;
+/// Base address register flag: the BAR holds an I/O port, not memory.
+const BAR_IO: u32 = 1 << 0;
+/// Base address register type bits for a 64-bit memory BAR.
+const BAR_64_BIT: u32 = 0b10 << 1;
+
+/// The configuration address and data ports of the PCI host bridge.
+struct ConfigSpace {
+    address: Port<u32>,
+    data: Port<u32>,
+}
+
+static CONFIG_SPACE: Mutex<ConfigSpace> = Mutex::new(ConfigSpace {
+    address: Port::new(0xcf8),
+    data: Port::new(0xcfc),
+});
+
+/// A function of a device on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciDevice {
+    /// Read the 32-bit configuration register at `offset`, which must be a
+    /// multiple of 4.
+    pub fn read_config(&self, offset: u8) -> u32 {
+        let address = 1 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xfc) as u32;
+        interrupts::without_interrupts(|| {
+            let mut config = CONFIG_SPACE.lock();
+            unsafe {
+                config.address.write(address);
+                config.data.read()
+            }
+        })
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read_config(REG_ID) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read_config(REG_ID) >> 16) as u16
+    }
+
+    /// Returns the class and subclass codes.
+    pub fn class(&self) -> (u8, u8) {
+        let class = self.read_config(REG_CLASS);
+        ((class >> 24) as u8, (class >> 16) as u8)
+    }
+
+    fn header_type(&self) -> u8 {
+        (self.read_config(REG_HEADER_TYPE) >> 16) as u8
+    }
+
+    /// Returns the physical address of the memory region given by base
+    /// address register `index` (0-5), or `None` if that BAR is unused or
+    /// holds an I/O port.
+    pub fn memory_bar(&self, index: u8) -> Option<PhysAddr> {
+        let bar = self.read_config(REG_BAR0 + index * 4);
+        if bar & BAR_IO != 0 {
+            return None;
+        }
+        let mut address = (bar & !0xf) as u64;
+        if bar & BAR_64_BIT != 0 && index < 5 {
+            address |= (self.read_config(REG_BAR0 + (index + 1) * 4) as u64) << 32;
+        }
+        if address == 0 {
+            None
+        } else {
+            Some(PhysAddr::new(address))
+        }
+    }
+}
+
+
+/// Returns all functions of all devices on the PCI bus, found by checking
+/// every bus and slot.
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    (0..=255u8)
+        .flat_map(|bus| (0..32u8).map(move |device| PciDevice { bus, device, function: 0 }))
+        .filter(|device| device.vendor_id() != NO_VENDOR)
+        .flat_map(|device| {
+            let functions = if device.header_type() & MULTI_FUNCTION != 0 { 8 } else { 1 };
+            (0..functions).map(move |function| PciDevice { function, ..device })
+        })
+        .filter(|device| device.vendor_id() != NO_VENDOR)
+}
+
+/// Returns the first device with the given vendor and device IDs.
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    devices().find(|device| device.vendor_id() == vendor_id && device.device_id() == device_id)
+}
@@ -0,0 +1,101 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// The frequency (in Hz) of the oscillator driving the Intel 8253/8254
+/// programmable interval timer (PIT).
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// The longest delay (in milliseconds) that fits into the PIT's 16-bit
+/// counter.
+pub const MAX_WAIT_MS: u32 = 0xffff * 1000 / PIT_FREQUENCY;
+
+/// Mode/command register: channel 0, lobyte/hibyte access, mode 3
+/// (square wave generator), binary counting.
+const CHANNEL_0_PERIODIC: u8 = 0b0011_0110;
+/// Mode/command register: channel 2, lobyte/hibyte access, mode 0
+/// (interrupt on terminal count), binary counting.
+const CHANNEL_2_ONESHOT: u8 = 0b1011_0000;
+
+/// The I/O ports used to program the PIT. Channel 2 is used for
+/// calibration because its gate and output can be controlled and
+/// polled through port 0x61 without generating any interrupts.
+struct Pit {
+    channel_0: Port<u8>,
+    channel_2: Port<u8>,
+    command: Port<u8>,
+    gate: Port<u8>,
+}
+
+static PIT: Mutex<Pit> = Mutex::new(Pit {
+    channel_0: Port::new(0x40),
+    channel_2: Port::new(0x42),
+    command: Port::new(0x43),
+    gate: Port::new(0x61),
+});
+
+/// Program channel 0 to raise IRQ 0 periodically, `hz` times per second.
+/// Frequencies below about 19 Hz cannot be represented and are rounded up.
+pub fn start_periodic(hz: u32) {
+    let divisor = divisor(hz);
+    let mut pit = PIT.lock();
+    unsafe {
+        pit.command.write(CHANNEL_0_PERIODIC);
+        pit.channel_0.write(divisor as u8);
+        pit.channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Returns the channel 0 divisor that comes closest to `hz` interrupts per
+/// second, within the range of the 16-bit counter.
+fn divisor(hz: u32) -> u16 {
+    (PIT_FREQUENCY / hz.max(1)).max(1).min(0xffff) as u16
+}
+
+/// Busy-wait for `ms` milliseconds using PIT channel 2. This does not
+/// depend on interrupts, so it can be used at boot to calibrate other
+/// timers. Delays longer than `MAX_WAIT_MS` are split into several
+/// shorter waits.
+pub fn wait_ms(ms: u32) {
+    let mut remaining = ms;
+    while remaining > 0 {
+        let chunk = remaining.min(MAX_WAIT_MS);
+        wait_ticks((PIT_FREQUENCY as u64 * chunk as u64 / 1000) as u16);
+        remaining -= chunk;
+    }
+}
+
+/// Busy-wait until PIT channel 2 has counted down `ticks` ticks of the
+/// `PIT_FREQUENCY` oscillator.
+fn wait_ticks(ticks: u16) {
+    let mut pit = PIT.lock();
+    unsafe {
+        // disconnect the speaker and hold the gate low while programming
+        let gate = pit.gate.read() & !0b11;
+        pit.gate.write(gate);
+
+        pit.command.write(CHANNEL_2_ONESHOT);
+        pit.channel_2.write(ticks as u8);
+        pit.channel_2.write((ticks >> 8) as u8);
+
+        // raising the gate starts the countdown; the output (bit 5) goes
+        // high once the counter reaches zero
+        pit.gate.write(gate | 0b01);
+        while pit.gate.read() & 0x20 == 0 {}
+
+        pit.gate.write(gate);
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_divisor() {
+    assert_eq!(divisor(1000), 1193);
+    assert_eq!(divisor(100), 11931);
+    // too slow for the counter, rounded up to about 18.2 Hz
+    assert_eq!(divisor(10), 0xffff);
+    assert_eq!(divisor(0), 0xffff);
+    // faster than the oscillator
+    assert_eq!(divisor(PIT_FREQUENCY * 2), 1);
+}
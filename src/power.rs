@@ -0,0 +1,35 @@
+use x86_64::instructions::{self, interrupts, port::Port};
+use x86_64::structures::DescriptorTablePointer;
+
+use crate::ps2;
+
+/// Restart the machine. The PS/2 controller's reset line is tried first;
+/// if that does not work, the CPU is made to triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    ps2::pulse_reset_line();
+
+    // with an empty IDT, any interrupt becomes a triple fault
+    let empty_idt = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe { instructions::tables::lidt(&empty_idt) };
+    interrupts::int3();
+    unreachable!("Triple fault did not reset the machine");
+}
+
+/// Turn the machine off. Without ACPI support this only works on
+/// emulators, through the ports QEMU, Bochs and VirtualBox listen on;
+/// elsewhere the CPU is halted instead.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    unsafe {
+        // QEMU (newer versions)
+        Port::<u16>::new(0x604).write(0x2000);
+        // Bochs and older versions of QEMU
+        Port::<u16>::new(0xb004).write(0x2000);
+        // VirtualBox
+        Port::<u16>::new(0x4004).write(0x3400);
+    }
+    loop {
+        instructions::hlt();
+    }
+}
@@ -0,0 +1,437 @@
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::interrupts::{self as irq, InterruptIndex};
+use crate::time::{Duration, Instant};
+
+// Controller commands.
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xa7;
+const COMMAND_ENABLE_SECOND: u8 = 0xa8;
+const COMMAND_TEST_SECOND: u8 = 0xa9;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST: u8 = 0xab;
+const COMMAND_DISABLE_FIRST: u8 = 0xad;
+const COMMAND_ENABLE_FIRST: u8 = 0xae;
+const COMMAND_WRITE_SECOND: u8 = 0xd4;
+const COMMAND_PULSE_RESET: u8 = 0xfe;
+
+// Configuration byte flags.
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+// Status register flags.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+// Device commands common to keyboards and mice.
+const DEVICE_IDENTIFY: u8 = 0xf2;
+const DEVICE_ENABLE_SCANNING: u8 = 0xf4;
+const DEVICE_DISABLE_SCANNING: u8 = 0xf5;
+const DEVICE_RESET: u8 = 0xff;
+
+// Replies.
+const SELF_TEST_PASSED: u8 = 0x55;
+const INTERFACE_TEST_PASSED: u8 = 0x00;
+const DEVICE_SELF_TEST_PASSED: u8 = 0xaa;
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+
+/// How often to send a byte to a device that keeps asking for a resend.
+const MAX_RETRIES: usize = 3;
+/// How long to wait for the controller or a device to respond.
+const TIMEOUT: Duration = Duration::from_millis(50);
+/// How long to wait for a device to finish its self-test after a reset.
+const RESET_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The two ports of the PS/2 controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    /// The first port, normally the keyboard, on IRQ 1.
+    First,
+    /// The second port, normally the mouse, on IRQ 12.
+    Second,
+}
+
+impl Ps2Port {
+    /// Returns the IRQ line of the port.
+    pub fn irq(self) -> u8 {
+        match self {
+            Ps2Port::First => InterruptIndex::Keyboard.as_irq(),
+            Ps2Port::Second => InterruptIndex::Mouse.as_irq(),
+        }
+    }
+}
+
+/// The kinds of device that can be attached to a PS/2 port, as reported
+/// by the identify command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    /// An old AT keyboard, which does not answer the identify command.
+    AtKeyboard,
+    /// An MF2 keyboard, whose ID shows whether the controller translates
+    /// its scancodes.
+    Mf2Keyboard { translated: bool },
+    /// A standard mouse without a scroll wheel.
+    StandardMouse,
+    /// A mouse with a scroll wheel (IntelliMouse).
+    ScrollMouse,
+    /// A mouse with a scroll wheel and 5 buttons.
+    FiveButtonMouse,
+    /// A device answering with an ID we do not know.
+    Unknown(u8, u8),
+}
+
+impl DeviceType {
+    /// Interpret the bytes sent in reply to the identify command.
+    pub fn from_id(id: &[u8]) -> DeviceType {
+        match id {
+            [] => DeviceType::AtKeyboard,
+            [0x00] => DeviceType::StandardMouse,
+            [0x03] => DeviceType::ScrollMouse,
+            [0x04] => DeviceType::FiveButtonMouse,
+            [0xab, 0x41] | [0xab, 0xc1] => DeviceType::Mf2Keyboard { translated: true },
+            [0xab, 0x83] => DeviceType::Mf2Keyboard { translated: false },
+            [first] => DeviceType::Unknown(*first, 0),
+            [first, second, ..] => DeviceType::Unknown(*first, *second),
+        }
+    }
+
+    /// Returns whether the device is a keyboard.
+    pub fn is_keyboard(self) -> bool {
+        matches!(self, DeviceType::AtKeyboard | DeviceType::Mf2Keyboard { .. })
+    }
+
+    /// Returns whether the device is a mouse.
+    pub fn is_mouse(self) -> bool {
+        matches!(self,
+            DeviceType::StandardMouse | DeviceType::ScrollMouse | DeviceType::FiveButtonMouse)
+    }
+}
+
+/// Errors that can occur while talking to the PS/2 controller or devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller or device did not respond in time.
+    Timeout,
+    /// The controller failed its self-test, returning the given byte.
+    SelfTestFailed(u8),
+    /// The port failed its interface test, returning the given byte.
+    InterfaceTestFailed(Ps2Port, u8),
+    /// The device failed its self-test after a reset.
+    DeviceSelfTestFailed(Ps2Port, u8),
+    /// The device replied to a command with something other than an ACK.
+    NoAck(Ps2Port, u8),
+    /// There is no working device on the port.
+    NoDevice(Ps2Port),
+}
+
+/// The I/O ports of the Intel 8042 PS/2 controller.
+struct Controller {
+    data: Port<u8>,
+    /// Reads return the status register; writes send a command.
+    command: Port<u8>,
+    /// Whether the controller has a second port.
+    dual_channel: bool,
+    /// The type of the device on each port, if one was found.
+    devices: [Option<DeviceType>; 2],
+}
+
+static CONTROLLER: Mutex<Controller> = Mutex::new(Controller {
+    data: Port::new(0x60),
+    command: Port::new(0x64),
+    dual_channel: false,
+    devices: [None; 2],
+});
+
+impl Controller {
+    /// Read the status register.
+    fn status(&mut self) -> u8 {
+        unsafe { self.command.read() }
+    }
+
+    /// Wait until `flag` in the status register is set (or cleared).
+    fn wait_for_status(&mut self, flag: u8, set: bool, timeout: Duration)
+        -> Result<(), Ps2Error>
+    {
+        let deadline = Instant::now() + timeout;
+        while (self.status() & flag != 0) != set {
+            if Instant::now() > deadline {
+                return Err(Ps2Error::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a byte from the data port.
+    fn read(&mut self, timeout: Duration) -> Result<u8, Ps2Error> {
+        self.wait_for_status(STATUS_OUTPUT_FULL, true, timeout)?;
+        Ok(unsafe { self.data.read() })
+    }
+
+    /// Write a byte to the data port.
+    fn write(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_for_status(STATUS_INPUT_FULL, false, TIMEOUT)?;
+        unsafe { self.data.write(byte) };
+        Ok(())
+    }
+
+    /// Discard any bytes waiting in the output buffer.
+    fn flush(&mut self) {
+        while self.status() & STATUS_OUTPUT_FULL != 0 {
+            unsafe { self.data.read() };
+        }
+    }
+
+    /// Send a command to the controller itself.
+    fn send_controller_command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_for_status(STATUS_INPUT_FULL, false, TIMEOUT)?;
+        unsafe { self.command.write(command) };
+        Ok(())
+    }
+
+    /// Read the controller configuration byte.
+    fn read_config(&mut self) -> Result<u8, Ps2Error> {
+        self.send_controller_command(COMMAND_READ_CONFIG)?;
+        self.read(TIMEOUT)
+    }
+
+    /// Write the controller configuration byte.
+    fn write_config(&mut self, config: u8) -> Result<(), Ps2Error> {
+        self.send_controller_command(COMMAND_WRITE_CONFIG)?;
+        self.write(config)?;
+        // the keyboard's ID depends on whether it was identified with
+        // translation on, so keep it in line with the translation now used
+        if let Some(DeviceType::Mf2Keyboard { translated }) = &mut self.devices[0] {
+            *translated = config & CONFIG_TRANSLATION != 0;
+        }
+        Ok(())
+    }
+
+    /// Send a byte to the device on `port`, retrying while it asks for a
+    /// resend, and wait for its ACK.
+    fn send_to_device(&mut self, port: Ps2Port, byte: u8) -> Result<(), Ps2Error> {
+        let mut reply = RESEND;
+        for _ in 0..MAX_RETRIES {
+            if port == Ps2Port::Second {
+                self.send_controller_command(COMMAND_WRITE_SECOND)?;
+            }
+            self.write(byte)?;
+            reply = self.read(TIMEOUT)?;
+            if reply != RESEND {
+                break;
+            }
+        }
+        match reply {
+            ACK => Ok(()),
+            reply => Err(Ps2Error::NoAck(port, reply)),
+        }
+    }
+
+    /// Reset the device on `port` and wait for it to pass its self-test.
+    fn reset_device(&mut self, port: Ps2Port) -> Result<(), Ps2Error> {
+        self.send_to_device(port, DEVICE_RESET)?;
+        match self.read(RESET_TIMEOUT)? {
+            DEVICE_SELF_TEST_PASSED => {}
+            reply => return Err(Ps2Error::DeviceSelfTestFailed(port, reply)),
+        }
+        // mice follow the self-test result with their ID
+        let _ = self.read(TIMEOUT);
+        Ok(())
+    }
+
+    /// Ask the device on `port` what it is.
+    fn identify_device(&mut self, port: Ps2Port) -> Result<DeviceType, Ps2Error> {
+        self.send_to_device(port, DEVICE_DISABLE_SCANNING)?;
+        self.send_to_device(port, DEVICE_IDENTIFY)?;
+        let mut id = [0; 2];
+        let mut len = 0;
+        while len < id.len() {
+            match self.read(TIMEOUT) {
+                Ok(byte) => {
+                    id[len] = byte;
+                    len += 1;
+                }
+                Err(_) => break,
+            }
+        }
+        self.send_to_device(port, DEVICE_ENABLE_SCANNING)?;
+        Ok(DeviceType::from_id(&id[..len]))
+    }
+
+    /// Run the full controller initialization sequence.
+    fn init(&mut self) -> Result<(), Ps2Error> {
+        // disable both ports so devices cannot interfere, and throw away
+        // anything they already sent
+        self.send_controller_command(COMMAND_DISABLE_FIRST)?;
+        self.send_controller_command(COMMAND_DISABLE_SECOND)?;
+        self.flush();
+
+        // turn off IRQs and translation while we set things up
+        let mut config = self.read_config()?;
+        config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ | CONFIG_TRANSLATION);
+        self.write_config(config)?;
+
+        // the self-test may reset the controller, so restore the config
+        self.send_controller_command(COMMAND_SELF_TEST)?;
+        match self.read(TIMEOUT)? {
+            SELF_TEST_PASSED => {}
+            reply => return Err(Ps2Error::SelfTestFailed(reply)),
+        }
+        self.write_config(config)?;
+
+        // there is a second port if enabling it starts its clock
+        self.dual_channel = false;
+        if config & CONFIG_SECOND_CLOCK_DISABLED != 0 {
+            self.send_controller_command(COMMAND_ENABLE_SECOND)?;
+            self.dual_channel = self.read_config()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+            self.send_controller_command(COMMAND_DISABLE_SECOND)?;
+        }
+
+        // test the interfaces and enable the ports that work
+        let mut working = [false; 2];
+        for (i, &(port, test, enable)) in [
+            (Ps2Port::First, COMMAND_TEST_FIRST, COMMAND_ENABLE_FIRST),
+            (Ps2Port::Second, COMMAND_TEST_SECOND, COMMAND_ENABLE_SECOND),
+        ].iter().enumerate() {
+            if port == Ps2Port::Second && !self.dual_channel {
+                continue;
+            }
+            self.send_controller_command(test)?;
+            if self.read(TIMEOUT)? == INTERFACE_TEST_PASSED {
+                self.send_controller_command(enable)?;
+                working[i] = true;
+            }
+        }
+
+        // reset and identify the devices
+        for (i, &port) in [Ps2Port::First, Ps2Port::Second].iter().enumerate() {
+            self.devices[i] = None;
+            if working[i] && self.reset_device(port).is_ok() {
+                self.devices[i] = self.identify_device(port).ok();
+            }
+            self.flush();
+        }
+
+        // finally turn IRQs (and translation to scancode set 1) back on
+        let mut config = self.read_config()?;
+        if working[0] {
+            config |= CONFIG_FIRST_IRQ | CONFIG_TRANSLATION;
+        }
+        if working[1] {
+            config |= CONFIG_SECOND_IRQ;
+        }
+        self.write_config(config)?;
+
+        if self.devices.iter().all(Option::is_none) {
+            return Err(Ps2Error::NoDevice(Ps2Port::First));
+        }
+        Ok(())
+    }
+}
+
+
+/// Initialize the PS/2 controller and the devices attached to it. Must be
+/// called before the keyboard and mouse IRQ handlers are registered.
+pub fn init() -> Result<(), Ps2Error> {
+    interrupts::without_interrupts(|| CONTROLLER.lock().init())
+}
+
+/// Returns whether the controller has a second (mouse) port.
+pub fn has_second_port() -> bool {
+    CONTROLLER.lock().dual_channel
+}
+
+/// Returns the type of the device found on `port` during initialization.
+pub fn device_type(port: Ps2Port) -> Option<DeviceType> {
+    let index = match port {
+        Ps2Port::First => 0,
+        Ps2Port::Second => 1,
+    };
+    CONTROLLER.lock().devices[index]
+}
+
+/// Send a command and its arguments to the device on `port`, waiting for
+/// the device to acknowledge each byte, then read `response.len()` bytes
+/// of reply into `response`.
+///
+/// The port's IRQ is masked meanwhile, so that the interrupt handler does
+/// not consume the replies.
+pub fn send_command(port: Ps2Port, command: u8, args: &[u8], response: &mut [u8])
+    -> Result<(), Ps2Error>
+{
+    with_irq_masked(port.irq(), || {
+        let mut controller = CONTROLLER.lock();
+        controller.send_to_device(port, command)?;
+        for &arg in args {
+            controller.send_to_device(port, arg)?;
+        }
+        for byte in response.iter_mut() {
+            *byte = controller.read(TIMEOUT)?;
+        }
+        Ok(())
+    })
+}
+
+/// Enable or disable translation of the first port's scancodes from set
+/// 2 to set 1.
+pub fn set_translation(enabled: bool) -> Result<(), Ps2Error> {
+    with_irq_masked(Ps2Port::First.irq(), || {
+        let mut controller = CONTROLLER.lock();
+        let config = controller.read_config()?;
+        let config = if enabled {
+            config | CONFIG_TRANSLATION
+        } else {
+            config & !CONFIG_TRANSLATION
+        };
+        controller.write_config(config)
+    })
+}
+
+/// Ask the controller to pulse the CPU reset line. Returns if the
+/// controller does not react within the usual timeout.
+pub fn pulse_reset_line() {
+    let mut controller = CONTROLLER.lock();
+    if controller.send_controller_command(COMMAND_PULSE_RESET).is_ok() {
+        // give the reset some time to happen
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {}
+    }
+}
+
+/// Run `f` with the given IRQ line masked, restoring its previous state
+/// afterwards.
+fn with_irq_masked<F: FnOnce() -> R, R>(irq_line: u8, f: F) -> R {
+    let was_masked = irq::is_masked(irq_line);
+    irq::mask_irq(irq_line);
+    let result = interrupts::without_interrupts(f);
+    if !was_masked {
+        irq::unmask_irq(irq_line);
+    }
+    result
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_device_type_from_id() {
+    assert_eq!(DeviceType::from_id(&[]), DeviceType::AtKeyboard);
+    assert_eq!(DeviceType::from_id(&[0x03]), DeviceType::ScrollMouse);
+    assert_eq!(DeviceType::from_id(&[0xab, 0x41]),
+        DeviceType::Mf2Keyboard { translated: true });
+    assert_eq!(DeviceType::from_id(&[0x42, 0x43]), DeviceType::Unknown(0x42, 0x43));
+    assert!(DeviceType::from_id(&[0x00]).is_mouse());
+}
+
+#[test_case]
+fn test_keyboard_translation() {
+    let config = with_irq_masked(Ps2Port::First.irq(), || CONTROLLER.lock().read_config())
+        .expect("could not read the controller configuration");
+    if let Some(DeviceType::Mf2Keyboard { translated }) = device_type(Ps2Port::First) {
+        assert_eq!(translated, config & CONFIG_TRANSLATION != 0);
+    }
+}
@@ -0,0 +1,118 @@
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// Number of bytes a `ByteQueue` can hold. Must be a power of 2.
+pub const QUEUE_SIZE: usize = 256;
+
+/// A fixed-size, lock-free ring buffer of bytes for passing data from an
+/// interrupt handler to the rest of the kernel.
+///
+/// It is safe for one producer and one consumer to use the queue at the
+/// same time without any locking, which means an interrupt handler can
+/// push to it even while it interrupts the consumer in the middle of a
+/// `pop`. With more than one producer (or consumer), callers must
+/// serialize their accesses.
+pub struct ByteQueue {
+    buffer: [AtomicU8; QUEUE_SIZE],
+    /// Index of the next byte to pop. Only written by the consumer.
+    head: AtomicUsize,
+    /// Index of the next free slot. Only written by the producer.
+    tail: AtomicUsize,
+    /// Number of bytes dropped because the queue was full.
+    dropped: AtomicU64,
+}
+
+impl ByteQueue {
+    /// Creates an empty queue.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicU8 = AtomicU8::new(0);
+        ByteQueue {
+            buffer: [EMPTY; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Add a byte to the end of the queue. If the queue is full, the byte
+    /// is dropped, counted and returned as the error.
+    pub fn push(&self, byte: u8) -> Result<(), u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= QUEUE_SIZE {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(byte);
+        }
+        self.buffer[tail % QUEUE_SIZE].store(byte, Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove and return the byte at the front of the queue, if any.
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let byte = self.buffer[head % QUEUE_SIZE].load(Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+
+    /// Returns the number of bytes currently in the queue.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_queue_fifo_order() {
+    let queue = ByteQueue::new();
+    assert_eq!(queue.pop(), None);
+    for byte in 0..10 {
+        queue.push(byte).unwrap();
+    }
+    assert_eq!(queue.len(), 10);
+    for byte in 0..10 {
+        assert_eq!(queue.pop(), Some(byte));
+    }
+    assert!(queue.is_empty());
+}
+
+#[test_case]
+fn test_queue_overflow() {
+    let queue = ByteQueue::new();
+    for i in 0..QUEUE_SIZE {
+        queue.push(i as u8).unwrap();
+    }
+    assert_eq!(queue.push(42), Err(42));
+    assert_eq!(queue.dropped(), 1);
+    assert_eq!(queue.pop(), Some(0));
+    assert_eq!(queue.push(42), Ok(()));
+}
+
+#[test_case]
+fn test_queue_wraparound() {
+    let queue = ByteQueue::new();
+    for i in 0..(3 * QUEUE_SIZE) {
+        queue.push(i as u8).unwrap();
+        assert_eq!(queue.pop(), Some(i as u8));
+    }
+    assert!(queue.is_empty());
+}
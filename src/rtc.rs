@@ -0,0 +1,216 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::interrupts::{self as irq, InterruptIndex};
+use crate::time::DateTime;
+
+// CMOS registers holding the current date and time.
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+/// Century register. Its location is really given by the ACPI FADT, but
+/// 0x32 is where virtually all PCs (and QEMU) keep it.
+const REG_CENTURY: u8 = 0x32;
+
+// RTC status registers.
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+/// Status A: an update of the time registers is in progress.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: enable the periodic interrupt.
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Status B: enable the update-ended interrupt.
+const STATUS_B_UPDATE_INTERRUPT: u8 = 1 << 4;
+/// Status B: registers are in binary rather than BCD.
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Status B: hours are in 24-hour rather than 12-hour format.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// Flag set in the hours register for PM times in 12-hour format.
+const HOUR_PM: u8 = 1 << 7;
+
+/// Number of RTC interrupts received so far.
+static INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// The CMOS index and data ports, used to access the RTC registers.
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    /// Read a CMOS register.
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    /// Write a CMOS register.
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register);
+            self.data.write(value);
+        }
+    }
+
+    /// Returns whether the RTC is currently updating its registers.
+    fn update_in_progress(&mut self) -> bool {
+        self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    /// Read the raw values of the date and time registers, as
+    /// [seconds, minutes, hours, day, month, year, century].
+    fn read_raw(&mut self) -> [u8; 7] {
+        while self.update_in_progress() {}
+        [
+            self.read(REG_SECONDS),
+            self.read(REG_MINUTES),
+            self.read(REG_HOURS),
+            self.read(REG_DAY),
+            self.read(REG_MONTH),
+            self.read(REG_YEAR),
+            self.read(REG_CENTURY),
+        ]
+    }
+}
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+    index: Port::new(0x70),
+    data: Port::new(0x71),
+});
+
+/// The interrupts the RTC can raise on IRQ 8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcInterrupt {
+    /// Fire at `32768 >> (rate - 1)` Hz, for `rate` in 3..=15 (8192 Hz
+    /// down to 2 Hz).
+    Periodic { rate: u8 },
+    /// Fire once per second, after the time registers have been updated.
+    Update,
+}
+
+
+/// Read the current date and time from the RTC.
+pub fn read() -> DateTime {
+    let (raw, status_b) = interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        // the registers may change between reads, so keep reading until
+        // we get the same values twice in a row
+        let mut raw = cmos.read_raw();
+        loop {
+            let again = cmos.read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, cmos.read(REG_STATUS_B))
+    });
+
+    let [seconds, minutes, hours, day, month, year, century] = raw;
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let convert = |value: u8| if binary { value } else { bcd_to_binary(value) };
+
+    let pm = hours & HOUR_PM != 0;
+    let mut hour = convert(hours & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12-hour format: 12 AM is midnight and 12 PM is noon
+        hour = (hour % 12) + if pm { 12 } else { 0 };
+    }
+
+    let century = match convert(century) {
+        // no usable century register; assume the 21st century
+        0 => 20,
+        century => century,
+    };
+
+    DateTime {
+        year: century as u16 * 100 + convert(year) as u16,
+        month: convert(month),
+        day: convert(day),
+        hour,
+        minute: convert(minutes),
+        second: convert(seconds),
+        nanosecond: 0,
+    }
+}
+
+/// Enable an RTC interrupt on IRQ 8 and register its handler, which
+/// unmasks the line in the PIC.
+pub fn enable_interrupt(interrupt: RtcInterrupt) {
+    interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let mut status_b = cmos.read(REG_STATUS_B);
+        match interrupt {
+            RtcInterrupt::Periodic { rate } => {
+                let rate = rate.max(3).min(15);
+                let status_a = cmos.read(REG_STATUS_A);
+                cmos.write(REG_STATUS_A, (status_a & 0xf0) | rate);
+                status_b |= STATUS_B_PERIODIC_INTERRUPT;
+            }
+            RtcInterrupt::Update => status_b |= STATUS_B_UPDATE_INTERRUPT,
+        }
+        cmos.write(REG_STATUS_B, status_b);
+        // discard any interrupt that was already pending
+        cmos.read(REG_STATUS_C);
+    });
+    match irq::register_irq(InterruptIndex::Rtc.as_irq(), handle_interrupt) {
+        Ok(()) | Err(irq::IrqError::AlreadyRegistered) => {}
+        Err(err) => panic!("RTC IRQ registration failed: {:?}", err),
+    }
+}
+
+/// Disable all RTC interrupts and unregister the IRQ 8 handler.
+pub fn disable_interrupts() {
+    let _ = irq::unregister_irq(InterruptIndex::Rtc.as_irq(), handle_interrupt);
+    interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B,
+            status_b & !(STATUS_B_PERIODIC_INTERRUPT | STATUS_B_UPDATE_INTERRUPT));
+    });
+}
+
+/// Returns how many RTC interrupts have been received.
+pub fn interrupt_count() -> u64 {
+    INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// IRQ handler for the RTC. Status register C must be read after every
+/// interrupt, or the RTC will not raise another one.
+fn handle_interrupt(_irq: u8) {
+    CMOS.lock().read(REG_STATUS_C);
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Convert a binary-coded decimal value to binary.
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0f) + (value >> 4) * 10
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_bcd_to_binary() {
+    assert_eq!(bcd_to_binary(0x00), 0);
+    assert_eq!(bcd_to_binary(0x09), 9);
+    assert_eq!(bcd_to_binary(0x59), 59);
+}
+
+#[test_case]
+fn test_read_plausible_date() {
+    let now = read();
+    assert!(now.year >= 2000);
+    assert!(now.month >= 1 && now.month <= 12);
+    assert!(now.day >= 1 && now.day <= 31);
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
@@ -1,16 +1,86 @@
-use uart_16550::SerialPort;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::Waker;
+
+use futures_util::task::AtomicWaker;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+pub mod line;
+pub mod uart;
+
+pub use line::{LineDiscipline, Mode, MAX_LINE};
+pub use uart::{
+    ComPort, DataBits, Parity, SerialConfig, SerialError, StopBits, Uart, MAX_BAUD_RATE,
+};
+
+use crate::cmdline;
+use crate::emergency;
+use crate::interrupts as irq;
+use crate::warn;
+use crate::queue::ByteQueue;
+
+/// The port `read` and `try_read` take input from.
+pub const CONSOLE: ComPort = ComPort::Com1;
+
+/// Value in `ROUTES` for an output that is not sent anywhere.
+const NO_ROUTE: u8 = 0xff;
+
+/// The kinds of output that can be sent to different serial ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Test results and other messages printed with `serial_print!`.
+    Test,
+    /// The kernel log.
+    Log,
+    /// The remote debugger protocol.
+    Debugger,
+}
+
+impl Output {
+    /// All outputs, in order.
+    pub const ALL: [Output; 3] = [Output::Test, Output::Log, Output::Debugger];
+
+    /// Returns the name of the output, as used on the kernel command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Output::Test => "test",
+            Output::Log => "log",
+            Output::Debugger => "debug",
+        }
+    }
+}
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+    /// The serial ports, or `None` for those not present. Every port
+    /// found is set up with the default configuration.
+    static ref PORTS: [Mutex<Option<Uart>>; 4] = [
+        detect(ComPort::Com1),
+        detect(ComPort::Com2),
+        detect(ComPort::Com3),
+        detect(ComPort::Com4),
+    ];
 }
 
-/// Prints to the host through the serial interface.
+/// Bit mask of the ports that are present, indexed like `ComPort::ALL`.
+/// Lets the interrupt handler check for ports without locking them.
+static PRESENT: AtomicU8 = AtomicU8::new(0);
+/// The port each output is sent to, indexed like `Output::ALL`.
+static ROUTES: [AtomicU8; 3] = [AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0)];
+/// Bytes received on each port, queued by the interrupt handler.
+static RECEIVED: [ByteQueue; 4] = [EMPTY_QUEUE; 4];
+/// Waker of the task waiting for input on each port, if any.
+static WAKERS: [AtomicWaker; 4] = [NO_WAKER; 4];
+/// Line discipline applied to the input read with `read`. Only used
+/// outside of interrupt context.
+static LINE: Mutex<LineDiscipline> = Mutex::new(LineDiscipline::new());
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: ByteQueue = ByteQueue::new();
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Prints to the host through the serial port of the test output.
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
@@ -18,7 +88,8 @@ macro_rules! serial_print {
     };
 }
 
-/// Prints to the host through the serial interface, appending a newline.
+/// Prints to the host through the serial port of the test output,
+/// appending a newline.
 #[macro_export]
 macro_rules! serial_println {
     () => ($crate::serial_print!("\n"));
@@ -28,10 +99,239 @@ macro_rules! serial_println {
 
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
+    print_to(Output::Test, args);
+}
+
+/// Probe for a UART on `port` and set it up if there is one.
+fn detect(port: ComPort) -> Mutex<Option<Uart>> {
+    let mut uart = unsafe { Uart::new(port.base()) };
+    if !uart.probe() {
+        return Mutex::new(None);
+    }
+    uart.init(&SerialConfig::default()).expect("Default serial configuration is invalid");
+    PRESENT.fetch_or(1 << port.index(), Ordering::Relaxed);
+    Mutex::new(Some(uart))
+}
+
+/// Run `f` on the UART of `port`, or return `None` if it is not present.
+fn with_port<F: FnOnce(&mut Uart) -> R, R>(port: ComPort, f: F) -> Option<R> {
+    interrupts::without_interrupts(|| emergency::lock(&PORTS[port.index()]).as_mut().map(f))
+}
+
+/// Returns whether there is a UART on `port`.
+pub fn is_present(port: ComPort) -> bool {
+    lazy_static::initialize(&PORTS);
+    PRESENT.load(Ordering::Relaxed) & 1 << port.index() != 0
+}
+
+/// Change the line settings of `port`.
+pub fn configure(port: ComPort, config: &SerialConfig) -> Result<(), SerialError> {
+    with_port(port, |uart| {
+        uart.init(config)?;
+        uart.set_receive_interrupt(true);
+        Ok(())
+    }).unwrap_or(Err(SerialError::NotPresent))
+}
+
+/// Send `output` to `port`, or nowhere for `None`. Output sent to a port
+/// that is not present is dropped.
+pub fn route(output: Output, port: Option<ComPort>) {
+    let value = port.map_or(NO_ROUTE, |port| port.index() as u8);
+    ROUTES[output as usize].store(value, Ordering::Relaxed);
+}
+
+/// Returns the port `output` is sent to.
+pub fn output_port(output: Output) -> Option<ComPort> {
+    let index = ROUTES[output as usize].load(Ordering::Relaxed);
+    ComPort::ALL.get(index as usize).copied()
+}
+
+/// Write formatted text to the port of `output`.
+pub fn print_to(output: Output, args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    if let Some(port) = output_port(output) {
+        with_port(port, |uart| {
+            uart.write_fmt(args).expect("Printing to serial failed");
+        });
+    }
+}
+
+/// Like `print_to`, but drops the text instead of waiting if the port is
+/// in use. For interrupt handlers, which would otherwise deadlock when they
+/// interrupt code that is printing. Returns whether the text was written.
+pub fn try_print_to(output: Output, args: core::fmt::Arguments) -> bool {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
-    interrupts::without_interrupts(|| {
-        SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
+    if emergency::is_active() {
+        print_to(output, args);
+        return true;
+    }
+    let port = match output_port(output) {
+        Some(port) => port,
+        None => return true,
+    };
+    interrupts::without_interrupts(|| match PORTS[port.index()].try_lock() {
+        Some(mut uart) => {
+            if let Some(uart) = uart.as_mut() {
+                uart.write_fmt(args).expect("Printing to serial failed");
+            }
+            true
+        }
+        None => false,
+    })
+}
+
+/// Write raw bytes to `port`, without any formatting.
+pub fn write_bytes(port: ComPort, bytes: &[u8]) {
+    with_port(port, |uart| {
+        for &byte in bytes {
+            uart.send(byte);
+        }
     });
-}
\ No newline at end of file
+}
+
+
+// Input -----------------------------------------------------------------------
+
+/// Apply the serial options from the kernel command line, then register
+/// the interrupt handlers and turn on the receive interrupt of every port
+/// found.
+///
+/// `com1=115200,8n1` (etc.) configures a port, and `test=com2`, `log=com2`
+/// or `debug=com2` routes an output to a port (or `none`).
+pub fn init() {
+    for &port in ComPort::ALL.iter() {
+        if let Some(settings) = cmdline::value(port.name()) {
+            match SerialConfig::parse(settings) {
+                Some(config) => if let Err(err) = configure(port, &config) {
+                    warn!("Could not configure {}: {:?}", port.name(), err);
+                },
+                None => warn!("Invalid settings for {}: '{}'", port.name(), settings),
+            }
+        }
+    }
+    for &output in Output::ALL.iter() {
+        match cmdline::value(output.name()) {
+            Some("none") => route(output, None),
+            Some(name) => match ComPort::from_name(name) {
+                Some(port) => route(output, Some(port)),
+                None => warn!("Unknown serial port '{}' for {} output", name, output.name()),
+            },
+            None => {}
+        }
+    }
+
+    for &port in ComPort::ALL.iter().filter(|&&port| is_present(port)) {
+        match irq::register_irq(port.irq(), handle_interrupt) {
+            // COM3 and COM4 share their line with COM1 and COM2
+            Ok(()) | Err(irq::IrqError::AlreadyRegistered) => {}
+            Err(err) => panic!("Serial IRQ registration failed: {:?}", err),
+        }
+        with_port(port, |uart| uart.set_receive_interrupt(true));
+    }
+}
+
+/// IRQ handler for the serial ports. Queues every byte the UARTs on the
+/// line have received, so that the line discipline and echo run outside
+/// of interrupt context.
+fn handle_interrupt(irq: u8) {
+    let present = PRESENT.load(Ordering::Relaxed);
+    for &port in ComPort::ALL.iter() {
+        if port.irq() != irq || present & 1 << port.index() == 0 {
+            continue;
+        }
+        // the port may be locked by the code we interrupted, but reading
+        // the receive registers does not disturb a transmission
+        let mut uart = unsafe { Uart::new(port.base()) };
+        let mut received = false;
+        // the FIFO may hold several bytes by the time we get here
+        while let Some(byte) = uart.try_receive() {
+            // a full queue counts the dropped byte itself
+            received |= RECEIVED[port.index()].push(byte).is_ok();
+        }
+        if received {
+            WAKERS[port.index()].wake();
+        }
+    }
+}
+
+/// Remove and return the next raw byte received on `port`, bypassing the
+/// line discipline.
+///
+/// Each receive queue has a single consumer: for the `CONSOLE` port, use
+/// either this function or `read` and `try_read`, not both.
+pub fn pop_byte(port: ComPort) -> Option<u8> {
+    RECEIVED[port.index()].pop()
+}
+
+/// Like `pop_byte`, but also checks the UART itself when nothing is
+/// queued. For code running with interrupts disabled, which cannot wait
+/// for the interrupt handler to queue bytes.
+pub fn poll_byte(port: ComPort) -> Option<u8> {
+    if !is_present(port) {
+        return None;
+    }
+    // reading the receive registers does not disturb a transmission by
+    // whoever holds the port
+    pop_byte(port).or_else(|| unsafe { Uart::new(port.base()) }.try_receive())
+}
+
+/// Register a waker to be woken when the next byte arrives on `port`.
+pub fn register_waker(port: ComPort, waker: &Waker) {
+    WAKERS[port.index()].register(waker);
+}
+
+/// Feed bytes received on the console through the line discipline until
+/// input is ready, and copy it into `buffer`. Returns the number of bytes
+/// copied, or `None` if no input is ready yet.
+pub fn try_read(buffer: &mut [u8]) -> Option<usize> {
+    let mut line = LINE.lock();
+    while !line.is_ready() {
+        let byte = pop_byte(CONSOLE)?;
+        line.input(byte, &mut |bytes: &[u8]| write_bytes(CONSOLE, bytes));
+    }
+    line.take(buffer)
+}
+
+/// Waits for input on the console and copies it into `buffer`, halting
+/// the CPU while none is available. In canonical mode this returns a
+/// whole line, in raw mode a single byte. Must be called with interrupts
+/// enabled.
+pub fn read(buffer: &mut [u8]) -> usize {
+    loop {
+        if let Some(len) = try_read(buffer) {
+            return len;
+        }
+
+        // check for new input with interrupts disabled, so that a byte
+        // arriving between the check and the `hlt` cannot be missed
+        interrupts::disable();
+        if RECEIVED[CONSOLE.index()].is_empty() {
+            interrupts::enable_interrupts_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Returns the mode of the console's line discipline.
+pub fn mode() -> Mode {
+    LINE.lock().mode()
+}
+
+/// Switch the console's line discipline between canonical and raw mode.
+pub fn set_mode(mode: Mode) {
+    LINE.lock().set_mode(mode);
+}
+
+/// Turn echoing of console input on or off.
+pub fn set_echo(echo: bool) {
+    LINE.lock().set_echo(echo);
+}
+
+/// Returns the number of bytes received on `port` that were dropped
+/// because they arrived faster than they were read.
+pub fn overflow_count(port: ComPort) -> u64 {
+    RECEIVED[port.index()].dropped()
+}
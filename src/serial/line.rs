@@ -0,0 +1,209 @@
+/// Longest line that can be edited in canonical mode, including the
+/// final newline. Further input is ignored until the line is finished.
+pub const MAX_LINE: usize = 256;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+/// Ctrl+C: throw away the line being edited.
+const INTERRUPT: u8 = 0x03;
+/// Ctrl+D: finish the line without a newline.
+const END_OF_FILE: u8 = 0x04;
+/// Ctrl+U: erase the whole line.
+const KILL_LINE: u8 = 0x15;
+/// Ctrl+W: erase the word before the cursor.
+const ERASE_WORD: u8 = 0x17;
+
+/// How input is handed to readers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Input is collected and can be edited until a whole line has been
+    /// entered, like a terminal's cooked mode.
+    Canonical,
+    /// Every byte is handed to readers as soon as it arrives, unchanged.
+    Raw,
+}
+
+/// Turns the raw bytes received from a terminal into input for readers,
+/// echoing and editing as the mode requires.
+pub struct LineDiscipline {
+    mode: Mode,
+    echo: bool,
+    line: [u8; MAX_LINE],
+    len: usize,
+    /// Whether `line` holds finished input waiting to be read.
+    ready: bool,
+    /// Whether the previous byte was a carriage return, so that the line
+    /// feed of a "\r\n" pair does not end a second, empty line.
+    after_cr: bool,
+}
+
+impl LineDiscipline {
+    /// Creates a line discipline in canonical mode with echo on.
+    pub const fn new() -> Self {
+        LineDiscipline {
+            mode: Mode::Canonical,
+            echo: true,
+            line: [0; MAX_LINE],
+            len: 0,
+            ready: false,
+            after_cr: false,
+        }
+    }
+
+    /// Returns the current mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switch modes. Any partially edited line is discarded.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.len = 0;
+        self.ready = false;
+    }
+
+    /// Returns whether input is echoed back.
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Turn echoing of input on or off.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    /// Returns whether there is input ready to be taken with `take`.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Process one received byte, passing anything that should be echoed
+    /// to `output`. Returns whether input is now ready to be read. Bytes
+    /// arriving while input is ready are ignored, so readers should call
+    /// `take` before feeding more.
+    pub fn input(&mut self, byte: u8, output: &mut impl FnMut(&[u8])) -> bool {
+        if self.ready {
+            return true;
+        }
+
+        if self.mode == Mode::Raw {
+            if self.echo {
+                output(&[byte]);
+            }
+            self.line[0] = byte;
+            self.len = 1;
+            self.ready = true;
+            return true;
+        }
+
+        let after_cr = self.after_cr;
+        self.after_cr = byte == b'\r';
+        match byte {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                self.echo_bytes(b"\r\n", output);
+                self.push(b'\n');
+                self.ready = true;
+            }
+            END_OF_FILE => self.ready = true,
+            INTERRUPT => {
+                self.echo_bytes(b"^C\r\n", output);
+                self.len = 0;
+            }
+            BACKSPACE | DELETE => self.erase(1, output),
+            KILL_LINE => self.erase(self.len, output),
+            ERASE_WORD => {
+                let line = &self.line[..self.len];
+                let word_end = line.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+                let word_start = line[..word_end].iter()
+                    .rposition(|&b| b == b' ')
+                    .map_or(0, |i| i + 1);
+                self.erase(self.len - word_start, output);
+            }
+            // other control characters would confuse the terminal
+            0x00..=0x1f => {}
+            byte => {
+                // keep the last slot free for the newline
+                if self.len < MAX_LINE - 1 {
+                    self.push(byte);
+                    self.echo_bytes(&[byte], output);
+                }
+            }
+        }
+        self.ready
+    }
+
+    /// Copy the ready input into `buffer` and return its length. In
+    /// canonical mode this is a line ending in '\n' (unless it was ended
+    /// with Ctrl+D); in raw mode it is a single byte. Input that does not
+    /// fit into `buffer` is lost. Returns `None` if no input is ready.
+    pub fn take(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if !self.ready {
+            return None;
+        }
+        let len = self.len.min(buffer.len());
+        buffer[..len].copy_from_slice(&self.line[..len]);
+        self.len = 0;
+        self.ready = false;
+        Some(len)
+    }
+
+    /// Add a byte to the line.
+    fn push(&mut self, byte: u8) {
+        self.line[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// Remove up to `count` bytes from the end of the line, and from the
+    /// terminal.
+    fn erase(&mut self, count: usize, output: &mut impl FnMut(&[u8])) {
+        for _ in 0..count.min(self.len) {
+            self.len -= 1;
+            self.echo_bytes(b"\x08 \x08", output);
+        }
+    }
+
+    /// Pass `bytes` to `output` if echo is on.
+    fn echo_bytes(&self, bytes: &[u8], output: &mut impl FnMut(&[u8])) {
+        if self.echo {
+            output(bytes);
+        }
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        LineDiscipline::new()
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_canonical_line_editing() {
+    let mut line = LineDiscipline::new();
+    let mut echoed = 0;
+    for &byte in b"helo\x7flo wrld\x17world\r\n" {
+        line.input(byte, &mut |bytes: &[u8]| echoed += bytes.len());
+    }
+    assert!(line.is_ready());
+    let mut buffer = [0; 32];
+    let len = line.take(&mut buffer).unwrap();
+    assert_eq!(&buffer[..len], b"hello world\n");
+    assert!(echoed > 0);
+
+    // the "\n" of "\r\n" does not produce an empty second line
+    assert_eq!(line.take(&mut buffer), None);
+}
+
+#[test_case]
+fn test_raw_mode() {
+    let mut line = LineDiscipline::new();
+    line.set_mode(Mode::Raw);
+    line.set_echo(false);
+    let mut buffer = [0; 4];
+    assert!(line.input(0x7f, &mut |_: &[u8]| panic!("echo is off")));
+    assert_eq!(line.take(&mut buffer), Some(1));
+    assert_eq!(buffer[0], 0x7f);
+}
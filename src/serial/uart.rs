@@ -0,0 +1,289 @@
+use core::convert::TryFrom;
+use core::fmt;
+
+use x86_64::instructions::port::Port;
+
+use crate::interrupts::InterruptIndex;
+
+/// Frequency of the UART's baud rate generator divided by 16, i.e. the
+/// baud rate for a divisor of 1.
+pub const MAX_BAUD_RATE: u32 = 115_200;
+
+// Register offsets from the port's base address.
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+/// Line control flag: the data and interrupt enable registers hold the
+/// baud rate divisor instead.
+const LINE_DIVISOR_LATCH: u8 = 1 << 7;
+/// Interrupt enable flag: raise an interrupt when a byte is received.
+const INTERRUPT_DATA_AVAILABLE: u8 = 1 << 0;
+/// Enable and clear the FIFOs, interrupting once 14 bytes are queued.
+const FIFO_ENABLE_14: u8 = 0xc7;
+/// Set DTR and RTS, and OUT2, which connects the UART to the PIC.
+const MODEM_READY: u8 = 0x0b;
+/// Line status flag: there is a received byte to read.
+const STATUS_DATA_READY: u8 = 1 << 0;
+/// Line status flag: the transmit holding register is empty.
+const STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Errors that can occur when setting up a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// There is no UART at the port's address.
+    NotPresent,
+    /// The baud rate does not divide `MAX_BAUD_RATE`.
+    InvalidBaudRate,
+}
+
+/// The four standard serial ports of a PC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    /// All serial ports, in order.
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    /// Returns the port with the given name ("com1" to "com4").
+    pub fn from_name(name: &str) -> Option<ComPort> {
+        ComPort::ALL.iter().copied().find(|port| name.eq_ignore_ascii_case(port.name()))
+    }
+
+    /// Returns the name of the port.
+    pub fn name(self) -> &'static str {
+        match self {
+            ComPort::Com1 => "com1",
+            ComPort::Com2 => "com2",
+            ComPort::Com3 => "com3",
+            ComPort::Com4 => "com4",
+        }
+    }
+
+    /// Returns the base I/O port address of the port.
+    pub fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3f8,
+            ComPort::Com2 => 0x2f8,
+            ComPort::Com3 => 0x3e8,
+            ComPort::Com4 => 0x2e8,
+        }
+    }
+
+    /// Returns the IRQ line of the port. COM3 and COM4 share the lines of
+    /// COM1 and COM2.
+    pub fn irq(self) -> u8 {
+        match self {
+            ComPort::Com1 | ComPort::Com3 => InterruptIndex::Com1.as_irq(),
+            ComPort::Com2 | ComPort::Com4 => InterruptIndex::Com2.as_irq(),
+        }
+    }
+
+    /// Returns the position of the port in `ALL`.
+    pub(super) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Number of data bits per character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five = 0,
+    Six = 1,
+    Seven = 2,
+    Eight = 3,
+}
+
+/// Parity bit sent after the data bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None = 0b000,
+    Odd = 0b001,
+    Even = 0b011,
+    Mark = 0b101,
+    Space = 0b111,
+}
+
+/// Number of stop bits. With 5 data bits, `Two` means 1.5 stop bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One = 0,
+    Two = 1,
+}
+
+/// The line settings of a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// Must divide `MAX_BAUD_RATE`.
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl SerialConfig {
+    /// Parse settings written like "115200,8n1": the baud rate, then
+    /// optionally the data bits, parity (n, o, e, m or s) and stop bits.
+    pub fn parse(settings: &str) -> Option<SerialConfig> {
+        let mut parts = settings.splitn(2, ',');
+        let baud_rate = parts.next()?.parse().ok()?;
+        let mut config = SerialConfig { baud_rate, ..SerialConfig::default() };
+        if let Some(frame) = parts.next() {
+            let frame = frame.as_bytes();
+            if frame.len() != 3 {
+                return None;
+            }
+            config.data_bits = match frame[0] {
+                b'5' => DataBits::Five,
+                b'6' => DataBits::Six,
+                b'7' => DataBits::Seven,
+                b'8' => DataBits::Eight,
+                _ => return None,
+            };
+            config.parity = match frame[1].to_ascii_lowercase() {
+                b'n' => Parity::None,
+                b'o' => Parity::Odd,
+                b'e' => Parity::Even,
+                b'm' => Parity::Mark,
+                b's' => Parity::Space,
+                _ => return None,
+            };
+            config.stop_bits = match frame[2] {
+                b'1' => StopBits::One,
+                b'2' => StopBits::Two,
+                _ => return None,
+            };
+        }
+        config.divisor().map(|_| config)
+    }
+
+    /// Returns the baud rate divisor, or `None` if the baud rate cannot
+    /// be generated exactly or is too slow for the 16-bit divisor.
+    pub fn divisor(&self) -> Option<u16> {
+        if self.baud_rate == 0 || MAX_BAUD_RATE % self.baud_rate != 0 {
+            return None;
+        }
+        u16::try_from(MAX_BAUD_RATE / self.baud_rate).ok()
+    }
+
+    /// Returns the value of the line control register for these settings.
+    fn line_control(&self) -> u8 {
+        self.data_bits as u8 | (self.stop_bits as u8) << 2 | (self.parity as u8) << 3
+    }
+}
+
+impl Default for SerialConfig {
+    /// 38400 baud, 8 data bits, no parity, 1 stop bit.
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: 38_400,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+/// A 16550-compatible UART.
+pub struct Uart {
+    base: u16,
+}
+
+impl Uart {
+    /// Creates a driver for the UART at the given base I/O port.
+    ///
+    /// This function is unsafe because the caller must guarantee that
+    /// there is a UART at `base` and nothing else drives it.
+    pub const unsafe fn new(base: u16) -> Self {
+        Uart { base }
+    }
+
+    /// Returns the register at `offset` from the base address.
+    fn register(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+
+    /// Check whether there is a UART at the base address, by writing
+    /// patterns to its scratch register and reading them back. Nothing
+    /// answering on the port reads back as 0xff.
+    pub fn probe(&mut self) -> bool {
+        let mut scratch = self.register(SCRATCH);
+        [0x5a, 0xa5].iter().all(|&pattern| unsafe {
+            scratch.write(pattern);
+            scratch.read() == pattern
+        })
+    }
+
+    /// Set up the UART with the given line settings, with its interrupts
+    /// off. Fails if the baud rate cannot be generated.
+    pub fn init(&mut self, config: &SerialConfig) -> Result<(), SerialError> {
+        let divisor = config.divisor().ok_or(SerialError::InvalidBaudRate)?;
+        unsafe {
+            self.register(INTERRUPT_ENABLE).write(0);
+            self.register(LINE_CONTROL).write(LINE_DIVISOR_LATCH);
+            self.register(DATA).write(divisor as u8);
+            self.register(INTERRUPT_ENABLE).write((divisor >> 8) as u8);
+            self.register(LINE_CONTROL).write(config.line_control());
+            self.register(FIFO_CONTROL).write(FIFO_ENABLE_14);
+            self.register(MODEM_CONTROL).write(MODEM_READY);
+        }
+        Ok(())
+    }
+
+    /// Turn the interrupt for received data on or off.
+    pub fn set_receive_interrupt(&mut self, enabled: bool) {
+        let value = if enabled { INTERRUPT_DATA_AVAILABLE } else { 0 };
+        unsafe { self.register(INTERRUPT_ENABLE).write(value) };
+    }
+
+    /// Send a byte, waiting until the UART can take it.
+    pub fn send(&mut self, byte: u8) {
+        let mut line_status = self.register(LINE_STATUS);
+        while unsafe { line_status.read() } & STATUS_TRANSMIT_EMPTY == 0 {}
+        unsafe { self.register(DATA).write(byte) };
+    }
+
+    /// Returns the next received byte, if there is one.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if unsafe { self.register(LINE_STATUS).read() } & STATUS_DATA_READY == 0 {
+            return None;
+        }
+        Some(unsafe { self.register(DATA).read() })
+    }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_parse_config() {
+    let config = SerialConfig::parse("9600,7e2").unwrap();
+    assert_eq!(config.baud_rate, 9600);
+    assert_eq!(config.divisor(), Some(12));
+    assert_eq!(config.line_control(), 0b0001_1110);
+    assert_eq!(SerialConfig::parse("115200"),
+        Some(SerialConfig { baud_rate: 115_200, ..SerialConfig::default() }));
+    assert_eq!(SerialConfig::parse("12345"), None);
+    // divides evenly, but the divisor does not fit in 16 bits
+    assert_eq!(SerialConfig::parse("1"), None);
+    assert_eq!(SerialConfig::parse("2").and_then(|config| config.divisor()), Some(57_600));
+    assert_eq!(SerialConfig::parse("9600,8x1"), None);
+}
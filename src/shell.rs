@@ -0,0 +1,152 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+mod commands;
+pub mod editor;
+pub mod input;
+
+use editor::LineEditor;
+use input::Input;
+
+use crate::serial::{self, CONSOLE};
+use crate::vga_buffer::{self, Writer};
+
+/// The prompt printed before each line of input.
+const PROMPT: &str = "> ";
+/// The virtual console the shell runs on, leaving the kernel console
+/// (Alt+F1) to kernel messages.
+pub const SHELL_CONSOLE: usize = 1;
+/// Maximum number of commands that can be added with `register_command`.
+const MAX_COMMANDS: usize = 32;
+
+/// A function implementing a command. It gets the words on the command
+/// line after the command name, and writes its output to the console.
+pub type CommandFn = fn(console: &mut Console, args: &[&str]);
+
+/// A command the shell can run.
+#[derive(Clone, Copy)]
+pub struct Command {
+    /// The name the command is run with.
+    pub name: &'static str,
+    /// One line describing the command, shown by `help`.
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+/// Errors that can occur when registering commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellError {
+    /// A command with the same name already exists.
+    AlreadyRegistered,
+    /// All `MAX_COMMANDS` slots are in use.
+    NoFreeSlot,
+}
+
+/// Commands added by other parts of the kernel.
+static COMMANDS: Mutex<[Option<Command>; MAX_COMMANDS]> = Mutex::new([None; MAX_COMMANDS]);
+
+/// Add a command to the shell. Built-in commands cannot be replaced.
+pub fn register_command(command: Command) -> Result<(), ShellError> {
+    if find_command(command.name).is_some() {
+        return Err(ShellError::AlreadyRegistered);
+    }
+    interrupts::without_interrupts(|| {
+        let mut commands = COMMANDS.lock();
+        let free_slot = commands.iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ShellError::NoFreeSlot)?;
+        *free_slot = Some(command);
+        Ok(())
+    })
+}
+
+/// Remove a command added with `register_command`. Returns whether there
+/// was such a command.
+pub fn unregister_command(name: &str) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut commands = COMMANDS.lock();
+        match commands.iter_mut().find(|slot| matches!(slot, Some(c) if c.name == name)) {
+            Some(slot) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Returns the commands added with `register_command`.
+fn registered() -> Vec<Command> {
+    interrupts::without_interrupts(|| COMMANDS.lock().iter().flatten().copied().collect())
+}
+
+/// Returns the command with the given name, if there is one.
+fn find_command(name: &str) -> Option<Command> {
+    commands::BUILTINS.iter().copied()
+        .chain(registered())
+        .find(|command| command.name == name)
+}
+
+/// Output to both the shell's virtual console and the serial console.
+pub struct Console {
+    _private: (),
+}
+
+impl Console {
+    /// Run `f` with the writer of the shell's virtual console.
+    pub fn with_screen<R>(&mut self, f: impl FnOnce(&mut Writer) -> R) -> R {
+        vga_buffer::with_console(SHELL_CONSOLE, f)
+    }
+}
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.with_screen(|writer| writer.write_str(s))?;
+        // terminals need a carriage return to get back to the first column
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                serial::write_bytes(CONSOLE, b"\r\n");
+            }
+            serial::write_bytes(CONSOLE, part.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Run one line of input.
+fn execute(console: &mut Console, line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (*name, args),
+        None => return,
+    };
+    match find_command(name) {
+        Some(command) => (command.run)(console, args),
+        None => {
+            let _ = writeln!(console, "unknown command '{}'; try 'help'", name);
+        }
+    }
+}
+
+/// Read and run commands from the keyboard and the serial console,
+/// forever, showing the shell's virtual console. Takes over the serial
+/// console input, so nothing else may read it.
+pub async fn run() {
+    let mut console = Console { _private: () };
+    let mut input = Input::new(SHELL_CONSOLE);
+    vga_buffer::switch_console(SHELL_CONSOLE);
+    let mut editor = LineEditor::new();
+    loop {
+        let _ = console.write_str(PROMPT);
+        let line = loop {
+            let key = input.next_key().await;
+            if let Ok(Some(line)) = editor.key(key, &mut console) {
+                break line;
+            }
+        };
+        execute(&mut console, &line);
+    }
+}
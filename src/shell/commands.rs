@@ -0,0 +1,168 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::{Command, Console};
+use crate::allocator;
+use crate::interrupts::{self, exception_name};
+use crate::log::{self, ring, Level};
+use crate::power;
+use crate::serial::{self, CONSOLE};
+use crate::time;
+use crate::vga_buffer::Color;
+
+/// The commands every shell has.
+pub(super) const BUILTINS: &[Command] = &[
+    Command { name: "help", help: "list the available commands", run: help },
+    Command { name: "echo", help: "print the arguments", run: echo },
+    Command { name: "clear", help: "clear the screen", run: clear },
+    Command { name: "color", help: "set the text color: color <fg> [bg]", run: color },
+    Command { name: "meminfo", help: "show heap usage", run: meminfo },
+    Command { name: "uptime", help: "show the time since boot", run: uptime },
+    Command { name: "irqstat", help: "show interrupt counters", run: irqstat },
+    Command { name: "dmesg", help: "show recent log messages: dmesg [-c] [count]", run: dmesg },
+    Command { name: "loglevel", help: "show or set log levels: loglevel [target] <level>", run: loglevel },
+    Command { name: "reboot", help: "restart the machine", run: reboot },
+    Command { name: "shutdown", help: "turn the machine off", run: shutdown },
+];
+
+fn help(console: &mut Console, _args: &[&str]) {
+    let commands: Vec<Command> = BUILTINS.iter().copied().chain(super::registered()).collect();
+    let width = commands.iter().map(|command| command.name.len()).max().unwrap_or(0);
+    for command in commands {
+        let _ = writeln!(console, "  {:width$}  {}", command.name, command.help, width = width);
+    }
+}
+
+fn echo(console: &mut Console, args: &[&str]) {
+    for (i, arg) in args.iter().enumerate() {
+        let separator = if i == 0 { "" } else { " " };
+        let _ = write!(console, "{}{}", separator, arg);
+    }
+    let _ = writeln!(console);
+}
+
+fn clear(console: &mut Console, _args: &[&str]) {
+    console.with_screen(|writer| writer.clear());
+    // clear the terminal and move its cursor home
+    serial::write_bytes(CONSOLE, b"\x1b[2J\x1b[H");
+}
+
+fn color(console: &mut Console, args: &[&str]) {
+    if args.is_empty() || args.len() > 2 {
+        let _ = writeln!(console, "usage: color <foreground> [background]");
+        return;
+    }
+    let foreground = parse_color(console, args[0]);
+    let background = match args.get(1) {
+        Some(name) => parse_color(console, name),
+        None => Some(Color::Black),
+    };
+    if let (Some(foreground), Some(background)) = (foreground, background) {
+        console.with_screen(|writer| writer.set_color(foreground, background));
+    }
+}
+
+/// Look up a color by name, listing the valid names if there is none.
+fn parse_color(console: &mut Console, name: &str) -> Option<Color> {
+    let color = Color::from_name(name);
+    if color.is_none() {
+        let _ = write!(console, "unknown color '{}'; choose from:", name);
+        for color in Color::ALL.iter() {
+            let _ = write!(console, " {}", color.name());
+        }
+        let _ = writeln!(console);
+    }
+    color
+}
+
+fn meminfo(console: &mut Console, _args: &[&str]) {
+    let stats = allocator::stats();
+    let _ = writeln!(console, "heap size:  {:>6} KiB", stats.size / 1024);
+    let _ = writeln!(console, "allocated:  {:>6} KiB", stats.allocated / 1024);
+    let _ = writeln!(console, "reserved:   {:>6} KiB", stats.reserved / 1024);
+    let _ = writeln!(console, "free:       {:>6} KiB", (stats.size - stats.reserved) / 1024);
+}
+
+fn uptime(console: &mut Console, _args: &[&str]) {
+    let uptime = time::uptime();
+    let seconds = uptime.as_secs();
+    let _ = writeln!(console, "up {}:{:02}:{:02}.{:03}, {} timer ticks; it is now {}",
+        seconds / 3600, seconds / 60 % 60, seconds % 60, uptime.subsec_millis(),
+        time::ticks(), time::now());
+}
+
+fn irqstat(console: &mut Console, _args: &[&str]) {
+    let stats = interrupts::stats();
+    for (irq, &count) in stats.irqs.iter().enumerate().filter(|&(_, &count)| count > 0) {
+        let _ = writeln!(console, "IRQ {:>2}: {:>10}", irq, count);
+    }
+    for (vector, &count) in stats.exceptions.iter().enumerate().filter(|&(_, &count)| count > 0) {
+        let _ = writeln!(console, "{}: {}", exception_name(vector as u8), count);
+    }
+    let _ = writeln!(console, "APIC timer: {}", stats.apic_timer);
+    let _ = writeln!(console, "spurious: {} (master PIC), {} (slave PIC), {} (APIC)",
+        stats.spurious_master, stats.spurious_slave, stats.spurious_apic);
+    let _ = writeln!(console, "unhandled: {}", stats.unhandled);
+}
+
+fn dmesg(console: &mut Console, args: &[&str]) {
+    let mut clear = false;
+    let mut count = None;
+    for &arg in args {
+        match (arg, arg.parse()) {
+            ("-c", _) => clear = true,
+            (_, Ok(n)) if count.is_none() => count = Some(n),
+            _ => {
+                let _ = writeln!(console, "usage: dmesg [-c] [count]");
+                return;
+            }
+        }
+    }
+    let since = count.map_or(0, ring::tail_seq);
+    let next = ring::for_each_entry(since, |entry| {
+        let _ = writeln!(console, "{}", entry);
+    });
+    let dropped = ring::dropped();
+    if dropped > 0 {
+        let _ = writeln!(console, "({} messages could not be recorded)", dropped);
+    }
+    if clear {
+        ring::clear(next);
+    }
+}
+
+fn loglevel(console: &mut Console, args: &[&str]) {
+    let (target, name) = match args {
+        [] => {
+            let _ = writeln!(console, "log level: {}", log::level().map_or("off", Level::name));
+            return;
+        }
+        [name] => (None, *name),
+        [target, name] => (Some(*target), *name),
+        _ => {
+            let _ = writeln!(console, "usage: loglevel [target] <level>");
+            return;
+        }
+    };
+    let level = match Level::parse_filter(name) {
+        Some(level) => level,
+        None => {
+            let _ = writeln!(console, "unknown level '{}'; choose from: off error warn info debug trace", name);
+            return;
+        }
+    };
+    match target {
+        Some(target) => if let Err(err) = log::set_target_level(target, level) {
+            let _ = writeln!(console, "could not set the level of '{}': {:?}", target, err);
+        },
+        None => log::set_level(level),
+    }
+}
+
+fn reboot(_console: &mut Console, _args: &[&str]) {
+    power::reboot();
+}
+
+fn shutdown(_console: &mut Console, _args: &[&str]) {
+    power::shutdown();
+}
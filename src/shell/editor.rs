@@ -0,0 +1,211 @@
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+use super::input::Key;
+
+/// Longest line that can be entered, so that a line plus the prompt still
+/// fits on one row of the screen.
+pub const MAX_INPUT: usize = 72;
+/// Number of lines kept in the history.
+pub const HISTORY_SIZE: usize = 32;
+
+/// Edits a line of input on a terminal, with a history of earlier lines.
+///
+/// The terminal is updated with nothing but printable characters,
+/// backspaces (which only move the cursor) and newlines, so the same
+/// output works on the VGA screen and on a serial terminal.
+pub struct LineEditor {
+    line: Vec<char>,
+    /// Position of the cursor in `line`.
+    cursor: usize,
+    history: VecDeque<String>,
+    /// Position in `history` while browsing it; `history.len()` means the
+    /// line being written.
+    history_pos: usize,
+    /// The line being written, saved while browsing the history.
+    draft: String,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            history_pos: 0,
+            draft: String::new(),
+        }
+    }
+
+    /// Returns the lines in the history, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    /// Handle a key press, writing the changes to the terminal to `out`.
+    /// Returns the line once it has been entered; Ctrl+C returns an empty
+    /// line.
+    pub fn key(&mut self, key: Key, out: &mut impl Write) -> Result<Option<String>, fmt::Error> {
+        match key {
+            Key::Char(c) if self.line.len() < MAX_INPUT => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+                out.write_char(c)?;
+                self.redraw_tail(0, out)?;
+            }
+            Key::Char(_) => {}
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+                out.write_char('\x08')?;
+                self.redraw_tail(1, out)?;
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+                self.redraw_tail(1, out)?;
+            }
+            Key::Backspace | Key::Delete => {}
+            Key::Left if self.cursor > 0 => {
+                self.cursor -= 1;
+                out.write_char('\x08')?;
+            }
+            Key::Right if self.cursor < self.line.len() => {
+                out.write_char(self.line[self.cursor])?;
+                self.cursor += 1;
+            }
+            Key::Left | Key::Right => {}
+            Key::Home => self.move_to(0, out)?,
+            Key::End => self.move_to(self.line.len(), out)?,
+            Key::Up if self.history_pos > 0 => {
+                if self.history_pos == self.history.len() {
+                    self.draft = self.line.iter().collect();
+                }
+                self.history_pos -= 1;
+                let line = self.history[self.history_pos].clone();
+                self.replace_line(&line, out)?;
+            }
+            Key::Down if self.history_pos < self.history.len() => {
+                self.history_pos += 1;
+                let line = match self.history.get(self.history_pos) {
+                    Some(line) => line.clone(),
+                    None => core::mem::take(&mut self.draft),
+                };
+                self.replace_line(&line, out)?;
+            }
+            Key::Up | Key::Down => {}
+            Key::Enter => {
+                out.write_char('\n')?;
+                let line = self.take_line();
+                self.add_to_history(&line);
+                return Ok(Some(line));
+            }
+            Key::Interrupt => {
+                out.write_str("^C\n")?;
+                self.take_line();
+                return Ok(Some(String::new()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reprint the line from the cursor to its end, blanking `erased`
+    /// more characters after it, and move the cursor back.
+    fn redraw_tail(&self, erased: usize, out: &mut impl Write) -> fmt::Result {
+        let tail = &self.line[self.cursor..];
+        for &c in tail {
+            out.write_char(c)?;
+        }
+        for _ in 0..erased {
+            out.write_char(' ')?;
+        }
+        for _ in 0..tail.len() + erased {
+            out.write_char('\x08')?;
+        }
+        Ok(())
+    }
+
+    /// Move the cursor to `position` in the line.
+    fn move_to(&mut self, position: usize, out: &mut impl Write) -> fmt::Result {
+        while self.cursor > position {
+            self.cursor -= 1;
+            out.write_char('\x08')?;
+        }
+        while self.cursor < position {
+            out.write_char(self.line[self.cursor])?;
+            self.cursor += 1;
+        }
+        Ok(())
+    }
+
+    /// Erase the line on the terminal and replace it with `line`.
+    fn replace_line(&mut self, line: &str, out: &mut impl Write) -> fmt::Result {
+        self.move_to(0, out)?;
+        let old_len = self.line.len();
+        self.line = line.chars().take(MAX_INPUT).collect();
+        self.cursor = 0;
+        self.redraw_tail(old_len.saturating_sub(self.line.len()), out)?;
+        self.move_to(self.line.len(), out)
+    }
+
+    /// Return the line and start a new, empty one.
+    fn take_line(&mut self) -> String {
+        let line = self.line.drain(..).collect();
+        self.cursor = 0;
+        self.draft.clear();
+        self.history_pos = self.history.len();
+        line
+    }
+
+    /// Add a line to the history, unless it is empty or repeats the last
+    /// one.
+    fn add_to_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.back().map(String::as_str) == Some(line) {
+            return;
+        }
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(String::from(line));
+        self.history_pos = self.history.len();
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        LineEditor::new()
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[cfg(test)]
+fn type_keys(editor: &mut LineEditor, keys: &[Key]) -> Option<String> {
+    let mut out = String::new();
+    keys.iter().filter_map(|&key| editor.key(key, &mut out).unwrap()).last()
+}
+
+#[test_case]
+fn test_line_editing() {
+    let mut editor = LineEditor::new();
+    let keys = [
+        Key::Char('e'), Key::Char('h'), Key::Char('o'), Key::Left, Key::Left,
+        Key::Char('c'), Key::End, Key::Backspace, Key::Char('o'), Key::Home,
+        Key::Delete, Key::Char('e'), Key::Enter,
+    ];
+    assert_eq!(type_keys(&mut editor, &keys).as_deref(), Some("echo"));
+}
+
+#[test_case]
+fn test_history() {
+    let mut editor = LineEditor::new();
+    type_keys(&mut editor, &[Key::Char('a'), Key::Enter]);
+    type_keys(&mut editor, &[Key::Char('b'), Key::Enter]);
+    type_keys(&mut editor, &[Key::Char('b'), Key::Enter]);
+    assert_eq!(editor.history().count(), 2);
+
+    let line = type_keys(&mut editor, &[Key::Char('c'), Key::Up, Key::Up, Key::Down, Key::Enter]);
+    assert_eq!(line.as_deref(), Some("b"));
+    let line = type_keys(&mut editor, &[Key::Char('c'), Key::Up, Key::Down, Key::Enter]);
+    assert_eq!(line.as_deref(), Some("c"));
+}
@@ -0,0 +1,186 @@
+use core::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+
+use crate::keyboard::{self, DecodedKey, KeyCode};
+use crate::serial::{self, CONSOLE};
+
+const ESCAPE: u8 = 0x1b;
+
+/// A key press, as far as line editing is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Ctrl+C.
+    Interrupt,
+}
+
+impl Key {
+    /// Translate a key press from the PS/2 keyboard.
+    fn from_decoded(key: DecodedKey) -> Option<Key> {
+        match key {
+            DecodedKey::Unicode('\n') => Some(Key::Enter),
+            DecodedKey::Unicode('\u{8}') => Some(Key::Backspace),
+            DecodedKey::Unicode('\u{7f}') => Some(Key::Delete),
+            DecodedKey::Unicode('\u{3}') => Some(Key::Interrupt),
+            DecodedKey::Unicode(c) if !c.is_control() => Some(Key::Char(c)),
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => Some(Key::Left),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => Some(Key::Right),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => Some(Key::Up),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => Some(Key::Down),
+            DecodedKey::RawKey(KeyCode::Home) => Some(Key::Home),
+            DecodedKey::RawKey(KeyCode::End) => Some(Key::End),
+            _ => None,
+        }
+    }
+}
+
+/// Where `SerialDecoder` is in an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Normal,
+    /// After ESC.
+    Escape,
+    /// After ESC [ (or ESC O), with the numeric parameter read so far.
+    Sequence(u8),
+}
+
+/// Turns the bytes sent by a terminal into key presses, including the
+/// VT100/xterm escape sequences for the cursor keys.
+pub struct SerialDecoder {
+    state: State,
+    /// Whether the previous byte was a carriage return, so that the line
+    /// feed of a "\r\n" pair does not count as a second Enter.
+    after_cr: bool,
+}
+
+impl SerialDecoder {
+    pub const fn new() -> Self {
+        SerialDecoder { state: State::Normal, after_cr: false }
+    }
+
+    /// Feed one byte, returning a key once the byte completes one.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let after_cr = self.after_cr;
+        self.after_cr = byte == b'\r';
+
+        match self.state {
+            State::Normal => match byte {
+                ESCAPE => {
+                    self.state = State::Escape;
+                    None
+                }
+                b'\n' if after_cr => None,
+                b'\r' | b'\n' => Some(Key::Enter),
+                0x08 | 0x7f => Some(Key::Backspace),
+                0x03 => Some(Key::Interrupt),
+                0x20..=0x7e => Some(Key::Char(byte as char)),
+                _ => None,
+            },
+            State::Escape => {
+                self.state = match byte {
+                    b'[' | b'O' => State::Sequence(0),
+                    _ => State::Normal,
+                };
+                None
+            }
+            State::Sequence(param) => {
+                self.state = State::Normal;
+                match byte {
+                    b'0'..=b'9' => {
+                        self.state = State::Sequence(param.saturating_mul(10) + (byte - b'0'));
+                        None
+                    }
+                    b'A' => Some(Key::Up),
+                    b'B' => Some(Key::Down),
+                    b'C' => Some(Key::Right),
+                    b'D' => Some(Key::Left),
+                    b'H' => Some(Key::Home),
+                    b'F' => Some(Key::End),
+                    b'~' => match param {
+                        1 | 7 => Some(Key::Home),
+                        3 => Some(Key::Delete),
+                        4 | 8 => Some(Key::End),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Key presses from both the PS/2 keyboard, while a given virtual
+/// console is on the screen, and the serial console.
+///
+/// This consumes the console bytes, so while it is in use nothing else
+/// may read them.
+pub struct Input {
+    /// The virtual console whose key presses are read.
+    screen: usize,
+    serial: SerialDecoder,
+}
+
+impl Input {
+    pub const fn new(screen: usize) -> Self {
+        Input { screen, serial: SerialDecoder::new() }
+    }
+
+    /// Wait for the next key press from either source.
+    pub async fn next_key(&mut self) -> Key {
+        poll_fn(|context| self.poll_key(context)).await
+    }
+
+    fn poll_key(&mut self, context: &mut Context) -> Poll<Key> {
+        // fast path, avoids registering the wakers
+        if let Some(key) = self.try_next_key() {
+            return Poll::Ready(key);
+        }
+
+        // register before checking again, so input arriving in between
+        // still wakes the task
+        keyboard::register_key_waker(self.screen, context.waker());
+        serial::register_waker(CONSOLE, context.waker());
+        match self.try_next_key() {
+            Some(key) => Poll::Ready(key),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Returns the next key press that has already arrived, if any.
+    fn try_next_key(&mut self) -> Option<Key> {
+        while let Some(key) = keyboard::pop_key(self.screen) {
+            if let Some(key) = Key::from_decoded(key) {
+                return Some(key);
+            }
+        }
+        while let Some(byte) = serial::pop_byte(CONSOLE) {
+            if let Some(key) = self.serial.feed(byte) {
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_serial_escape_sequences() {
+    let mut decoder = SerialDecoder::new();
+    let keys = b"a\x1b[A\x1b[3~\x7f\r\n"
+        .iter()
+        .filter_map(|&byte| decoder.feed(byte));
+    let expected = [Key::Char('a'), Key::Up, Key::Delete, Key::Backspace, Key::Enter];
+    assert!(keys.eq(expected.iter().copied()));
+}
@@ -0,0 +1,54 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+pub mod executor;
+pub mod keyboard;
+
+/// A unique identifier for a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    /// Creates a new, never before used task ID.
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+
+/// A kernel task: a future pinned on the heap, which the executor polls
+/// until it completes.
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    /// Whether the task's ID is in the executor's task queue, so that
+    /// waking it again does not queue it twice. Shared with its waker.
+    queued: Arc<AtomicBool>,
+}
+
+impl Task {
+    /// Creates a new task from a future (typically from an `async fn`).
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+            queued: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Returns the ID of this task.
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Poll the future of this task once.
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
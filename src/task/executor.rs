@@ -0,0 +1,193 @@
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use super::{Task, TaskId};
+
+/// Maximum number of tasks an executor can run at once. Each task is in
+/// the task queue at most once, so the queue can never overflow.
+const TASK_QUEUE_SIZE: usize = 100;
+
+/// A cooperative executor that only polls tasks after they have been
+/// woken, and halts the CPU while no task is ready.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    /// IDs of the tasks that are ready to be polled. Shared with the
+    /// wakers, which may push to it from interrupt context.
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    /// Creates an executor without any tasks.
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// Add a task to the executor. It is polled for the first time the
+    /// next time the executor runs.
+    ///
+    /// Panics if the executor already has `TASK_QUEUE_SIZE` tasks.
+    pub fn spawn(&mut self, task: Task) {
+        assert!(self.tasks.len() < TASK_QUEUE_SIZE, "too many tasks");
+        let task_id = task.id;
+        task.queued.store(true, Ordering::SeqCst);
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    /// Run tasks forever, sleeping whenever none of them is ready.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Poll every task that has been woken since the last call, removing
+    /// the ones that complete.
+    fn run_ready_tasks(&mut self) {
+        // destructure `self` to avoid borrow checker errors
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        while let Ok(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            // clear the flag before polling, so that a wakeup during the
+            // poll queues the task again
+            task.queued.store(false, Ordering::SeqCst);
+            let waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| {
+                    TaskWaker::new(task_id, task.queued.clone(), task_queue.clone())
+                });
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    // task done -> remove it and its cached waker
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    /// Halt the CPU until the next interrupt if no task is ready.
+    fn sleep_if_idle(&self) {
+        // check the queue with interrupts disabled, so that a wakeup from
+        // an interrupt handler between the check and the `hlt` cannot be
+        // lost; `enable_interrupts_and_hlt` re-enables them atomically
+        interrupts::disable();
+        if self.task_queue.is_empty() {
+            interrupts::enable_interrupts_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Executor::new()
+    }
+}
+
+
+/// Wakes a task by pushing its ID to the executor's task queue.
+struct TaskWaker {
+    task_id: TaskId,
+    /// The task's `queued` flag.
+    queued: Arc<AtomicBool>,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    /// Creates a `Waker` for the given task.
+    fn new(task_id: TaskId, queued: Arc<AtomicBool>,
+        task_queue: Arc<ArrayQueue<TaskId>>) -> Waker
+    {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            queued,
+            task_queue,
+        }))
+    }
+
+    /// Mark the task as ready to be polled, unless it already is. Wakers
+    /// are called from interrupt handlers, so this must not panic.
+    fn wake_task(&self) {
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            // cannot fail, since every task is queued at most once and
+            // `spawn` keeps the number of tasks within the queue's size
+            let _ = self.task_queue.push(self.task_id);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_spawn_wake_run() {
+    use alloc::rc::Rc;
+    use core::{cell::Cell, future::Future, pin::Pin};
+
+    /// Wakes itself many times on the first poll, and completes on the
+    /// second.
+    struct WakeSelf {
+        polls: Rc<Cell<usize>>,
+    }
+
+    impl Future for WakeSelf {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            self.polls.set(self.polls.get() + 1);
+            if self.polls.get() > 1 {
+                return Poll::Ready(());
+            }
+            // more wakeups than the queue holds, as from a busy interrupt
+            for _ in 0..2 * TASK_QUEUE_SIZE {
+                context.waker().wake_by_ref();
+            }
+            Poll::Pending
+        }
+    }
+
+    let polls = Rc::new(Cell::new(0));
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(WakeSelf { polls: polls.clone() }));
+    executor.spawn(Task::new(async {}));
+    executor.run_ready_tasks();
+
+    assert_eq!(polls.get(), 2);
+    assert!(executor.tasks.is_empty());
+    assert!(executor.waker_cache.is_empty());
+    assert!(executor.task_queue.is_empty());
+}
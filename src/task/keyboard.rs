@@ -0,0 +1,42 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::stream::Stream;
+
+use crate::keyboard::{self, DecodedKey};
+
+/// A stream of the key presses for one virtual console, decoded from the
+/// scancodes received by the keyboard interrupt handler. There should only
+/// be one of these per console, since the key presses are removed from the
+/// console's queue as they are read.
+pub struct KeyStream {
+    console: usize,
+}
+
+impl KeyStream {
+    /// Creates a stream of the key presses for virtual console `console`.
+    pub fn new(console: usize) -> Self {
+        KeyStream { console }
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = DecodedKey;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<DecodedKey>> {
+        // fast path, avoids registering the waker
+        if let Some(key) = keyboard::pop_key(self.console) {
+            return Poll::Ready(Some(key));
+        }
+
+        // register before checking again, so a key press arriving in
+        // between still wakes the task
+        keyboard::register_key_waker(self.console, context.waker());
+        match keyboard::pop_key(self.console) {
+            Some(key) => Poll::Ready(Some(key)),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub use core::time::Duration;
+
+pub mod datetime;
+pub mod tsc;
+
+pub use datetime::{DateTime, Weekday};
+
+use crate::interrupts::{self, InterruptIndex};
+use crate::pit;
+use crate::rtc;
+
+/// How many times per second the PIT raises the timer interrupt.
+pub const TIMER_HZ: u32 = 100;
+
+/// Wall-clock time at boot, in seconds since the Unix epoch.
+static BOOT_UNIX_SECONDS: AtomicU64 = AtomicU64::new(0);
+/// Number of timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Initialize the kernel's clocks. Must be called before any `Instant`
+/// is taken.
+pub fn init() {
+    tsc::init();
+    BOOT_UNIX_SECONDS.store(rtc::read().unix_timestamp(), Ordering::SeqCst);
+
+    pit::start_periodic(TIMER_HZ);
+    interrupts::register_irq(InterruptIndex::Timer.as_irq(), timer_tick)
+        .expect("Timer IRQ registration failed");
+}
+
+/// Returns the number of timer interrupts since boot; there are
+/// `TIMER_HZ` of them per second.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// IRQ handler for the PIT timer interrupt.
+fn timer_tick(_irq: u8) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the time elapsed since the clocks were initialized.
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant::boot())
+}
+
+/// Returns the current date and time in UTC. The date is read from the RTC
+/// once at boot and then advanced with the monotonic clock, so it never
+/// goes backwards and has sub-second resolution.
+pub fn now() -> DateTime {
+    let uptime = uptime();
+    DateTime::from_unix_timestamp(
+        BOOT_UNIX_SECONDS.load(Ordering::Relaxed) + uptime.as_secs(),
+        uptime.subsec_nanos())
+}
+
+
+/// A measurement of the monotonic clock, with nanosecond resolution.
+/// Backed by the time stamp counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns an instant corresponding to "now".
+    pub fn now() -> Instant {
+        Instant(tsc::read())
+    }
+
+    /// Returns the instant at which the clocks were initialized.
+    pub fn boot() -> Instant {
+        Instant(tsc::boot_ticks())
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to this instant,
+    /// or zero if `earlier` is later than this instant.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(tsc::ticks_to_ns(self.0.saturating_sub(earlier.0)))
+    }
+
+    /// Returns the amount of time elapsed since this instant was created.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns `Some(t)` where `t` is `self + duration`, or `None` if that
+    /// cannot be represented.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(tsc::ns_to_ticks(duration_to_ns(duration)?))
+            .map(Instant)
+    }
+
+    /// Returns `Some(t)` where `t` is `self - duration`, or `None` if that
+    /// cannot be represented.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(tsc::ns_to_ticks(duration_to_ns(duration)?))
+            .map(Instant)
+    }
+
+    /// Returns the raw TSC value of this instant.
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        self.checked_add(other).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, other: Duration) -> Instant {
+        self.checked_sub(other).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, other: Instant) -> Duration {
+        self.duration_since(other)
+    }
+}
+
+/// Convert a duration to whole nanoseconds, if it fits in a u64.
+fn duration_to_ns(duration: Duration) -> Option<u64> {
+    duration.as_secs()
+        .checked_mul(1_000_000_000)?
+        .checked_add(duration.subsec_nanos() as u64)
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_instant_monotonic() {
+    let first = Instant::now();
+    let second = Instant::now();
+    assert!(second >= first);
+    assert_eq!(first.duration_since(second), Duration::from_nanos(0));
+}
+
+#[test_case]
+fn test_instant_measures_pit_wait() {
+    let start = Instant::now();
+    crate::pit::wait_ms(20);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(10));
+    assert!(elapsed <= Duration::from_millis(200));
+}
+
+#[test_case]
+fn test_instant_duration_arithmetic() {
+    let now = Instant::now();
+    let later = now + Duration::from_millis(5);
+    let diff = (later - now).as_micros();
+    assert!(diff >= 4999 && diff <= 5001);
+}
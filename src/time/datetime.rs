@@ -0,0 +1,137 @@
+use core::fmt;
+
+/// Number of seconds in a day.
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A calendar date and time of day in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+/// The days of the week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl DateTime {
+    /// Create a DateTime from the number of seconds (and nanoseconds)
+    /// since the Unix epoch, 1970-01-01T00:00:00Z.
+    pub fn from_unix_timestamp(seconds: u64, nanosecond: u32) -> DateTime {
+        let days = seconds / SECONDS_PER_DAY;
+        let time_of_day = seconds % SECONDS_PER_DAY;
+        let (year, month, day) = civil_from_days(days as i64);
+
+        DateTime {
+            year: year as u16,
+            month,
+            day,
+            hour: (time_of_day / 3600) as u8,
+            minute: (time_of_day / 60 % 60) as u8,
+            second: (time_of_day % 60) as u8,
+            nanosecond,
+        }
+    }
+
+    /// Returns the number of whole seconds since the Unix epoch. Dates
+    /// before 1970 are clamped to 0.
+    pub fn unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day).max(0) as u64;
+        days * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    /// Returns the day of the week.
+    pub fn weekday(&self) -> Weekday {
+        // 1970-01-01 was a Thursday
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        match (days + 3).rem_euclid(7) {
+            0 => Weekday::Monday,
+            1 => Weekday::Tuesday,
+            2 => Weekday::Wednesday,
+            3 => Weekday::Thursday,
+            4 => Weekday::Friday,
+            5 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats the DateTime in ISO 8601 format, e.g.
+    /// `2020-06-01T13:45:30.250Z`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day,
+            self.hour, self.minute, self.second,
+            self.nanosecond / 1_000_000)
+    }
+}
+
+/// Returns the number of days since 1970-01-01 for the given date in the
+/// proleptic Gregorian calendar. See here for explanation:
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+        + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the (year, month, day) for the given number of days since
+/// 1970-01-01. See here for explanation:
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_datetime_epoch() {
+    let epoch = DateTime::from_unix_timestamp(0, 0);
+    assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+    assert_eq!((epoch.hour, epoch.minute, epoch.second), (0, 0, 0));
+    assert_eq!(epoch.weekday(), Weekday::Thursday);
+}
+
+#[test_case]
+fn test_datetime_roundtrip() {
+    // 2020-02-29T23:59:59Z, a leap day
+    let timestamp = 1_583_020_799;
+    let datetime = DateTime::from_unix_timestamp(timestamp, 0);
+    assert_eq!((datetime.year, datetime.month, datetime.day), (2020, 2, 29));
+    assert_eq!((datetime.hour, datetime.minute, datetime.second), (23, 59, 59));
+    assert_eq!(datetime.weekday(), Weekday::Saturday);
+    assert_eq!(datetime.unix_timestamp(), timestamp);
+}
@@ -0,0 +1,138 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use x86_64::instructions::interrupts;
+
+use crate::pit;
+
+/// How long to count TSC ticks against the PIT when CPUID does not report
+/// the TSC frequency.
+const CALIBRATION_MS: u32 = 50;
+
+/// Frequency of the TSC in Hz, or 0 before `init` has been called.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// Where the value in `FREQUENCY` came from, as a `FrequencySource`.
+static FREQUENCY_SOURCE: AtomicU8 = AtomicU8::new(FrequencySource::Pit as u8);
+/// Whether the TSC ticks at a constant rate in all power states.
+static INVARIANT: AtomicBool = AtomicBool::new(false);
+/// Value of the TSC when `init` was called.
+static BOOT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// How the TSC frequency was determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrequencySource {
+    /// Reported by CPUID leaf 0x15 (or 0x16).
+    Cpuid,
+    /// Measured against the PIT.
+    Pit,
+}
+
+
+/// Detect the TSC's capabilities and determine its frequency, preferring
+/// the value reported by CPUID and falling back to calibrating against
+/// the PIT.
+pub fn init() {
+    BOOT_TICKS.store(read(), Ordering::SeqCst);
+    INVARIANT.store(detect_invariant(), Ordering::SeqCst);
+
+    let (frequency, source) = match cpuid_frequency() {
+        Some(frequency) => (frequency, FrequencySource::Cpuid),
+        None => (calibrate_against_pit(), FrequencySource::Pit),
+    };
+    FREQUENCY.store(frequency, Ordering::SeqCst);
+    FREQUENCY_SOURCE.store(source as u8, Ordering::SeqCst);
+}
+
+/// Read the current value of the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Returns the TSC value recorded when the clock was initialized.
+pub fn boot_ticks() -> u64 {
+    BOOT_TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the frequency of the TSC in Hz.
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Returns how the TSC frequency was determined.
+pub fn frequency_source() -> FrequencySource {
+    match FREQUENCY_SOURCE.load(Ordering::Relaxed) {
+        0 => FrequencySource::Cpuid,
+        _ => FrequencySource::Pit,
+    }
+}
+
+/// Returns whether the TSC is invariant, i.e. it ticks at a constant rate
+/// regardless of frequency scaling and sleep states.
+pub fn is_invariant() -> bool {
+    INVARIANT.load(Ordering::Relaxed)
+}
+
+/// Convert a number of TSC ticks to nanoseconds.
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    match frequency() {
+        0 => 0,
+        frequency => (ticks as u128 * 1_000_000_000 / frequency as u128) as u64,
+    }
+}
+
+/// Convert a number of nanoseconds to TSC ticks.
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    (ns as u128 * frequency() as u128 / 1_000_000_000) as u64
+}
+
+/// CPUID.80000007h:EDX[8] indicates an invariant TSC.
+fn detect_invariant() -> bool {
+    let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_extended_leaf < 0x8000_0007 {
+        return false;
+    }
+    unsafe { __cpuid(0x8000_0007) }.edx & (1 << 8) != 0
+}
+
+/// Get the TSC frequency from CPUID, if the CPU reports it.
+///
+/// Leaf 0x15 gives the ratio of the TSC to the core crystal clock, and on
+/// most CPUs the crystal frequency as well. When the crystal frequency is
+/// missing, leaf 0x16 gives the processor base frequency, which the TSC
+/// runs at on those CPUs.
+fn cpuid_frequency() -> Option<u64> {
+    let max_leaf = unsafe { __cpuid(0x0) }.eax;
+    if max_leaf < 0x15 {
+        return None;
+    }
+
+    let leaf_15 = unsafe { __cpuid(0x15) };
+    let (denominator, numerator, crystal_hz) =
+        (leaf_15.eax as u64, leaf_15.ebx as u64, leaf_15.ecx as u64);
+    if denominator == 0 || numerator == 0 {
+        return None;
+    }
+    if crystal_hz != 0 {
+        return Some(crystal_hz * numerator / denominator);
+    }
+
+    if max_leaf >= 0x16 {
+        let base_mhz = (unsafe { __cpuid(0x16) }.eax & 0xffff) as u64;
+        if base_mhz != 0 {
+            return Some(base_mhz * 1_000_000);
+        }
+    }
+    None
+}
+
+/// Measure the TSC frequency by counting ticks over `CALIBRATION_MS`
+/// milliseconds of PIT time.
+fn calibrate_against_pit() -> u64 {
+    interrupts::without_interrupts(|| {
+        let start = read();
+        pit::wait_ms(CALIBRATION_MS);
+        let end = read();
+        (end - start) * 1000 / CALIBRATION_MS as u64
+    })
+}
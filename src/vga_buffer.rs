@@ -4,6 +4,32 @@ use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::instructions::interrupts;
 
+use crate::emergency;
+use crate::framebuffer;
+
+mod ansi;
+mod console;
+pub mod cp437;
+mod cursor;
+mod screen;
+mod scrollback;
+
+use ansi::{Action, AnsiParser};
+use scrollback::Scrollback;
+
+pub use console::{
+    active_console, console, switch_console, with_console, CONSOLE_COUNT,
+};
+pub use cursor::CursorShape;
+pub use screen::{
+    draw_box, fill_rect, restore_screen, save_screen, set_scroll_region, write_at,
+    BoxStyle, Rect, Screen,
+};
+pub use scrollback::{
+    init_scrollback, scroll_back, scroll_forward, set_scrollback_lines, DEFAULT_LINES,
+    MAX_LINES,
+};
+
 /// A u8 byte representing a color in VGA text mode.
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,14 +53,52 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    /// All colors, in order of their value.
+    pub const ALL: [Color; 16] = [
+        Color::Black, Color::Blue, Color::Green, Color::Cyan,
+        Color::Red, Color::Magenta, Color::Brown, Color::LightGray,
+        Color::DarkGray, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+        Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+    ];
+
+    /// Returns the color with the given name, written in lowercase
+    /// without spaces (e.g. "lightgray"). Case is ignored.
+    pub fn from_name(name: &str) -> Option<Color> {
+        Color::ALL.iter().copied().find(|color| name.eq_ignore_ascii_case(color.name()))
+    }
+
+    /// Returns the name of the color, as accepted by `from_name`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::Black => "black",
+            Color::Blue => "blue",
+            Color::Green => "green",
+            Color::Cyan => "cyan",
+            Color::Red => "red",
+            Color::Magenta => "magenta",
+            Color::Brown => "brown",
+            Color::LightGray => "lightgray",
+            Color::DarkGray => "darkgray",
+            Color::LightBlue => "lightblue",
+            Color::LightGreen => "lightgreen",
+            Color::LightCyan => "lightcyan",
+            Color::LightRed => "lightred",
+            Color::Pink => "pink",
+            Color::Yellow => "yellow",
+            Color::White => "white",
+        }
+    }
+}
+
 /// A u8 byte representing a color code (foreground and background
 /// color) in VGA text mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    pub fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
 }
@@ -51,8 +115,13 @@ struct ScreenChar {
 }
 
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+/// Number of rows in the VGA text buffer.
+pub const BUFFER_HEIGHT: usize = 25;
+/// Number of columns in the VGA text buffer.
+pub const BUFFER_WIDTH: usize = 80;
+
+/// Address of the VGA text buffer.
+const VGA_BUFFER: usize = 0xb8000;
 
 /// An array of arrays that keeps track of the characters in
 /// the buffer (outer array represents rows, inner array
@@ -64,59 +133,150 @@ struct Buffer {
 
 
 /// Takes care of writing characters to the VGA text buffer.
+///
+/// Text is normally written to the bottom row of the scrolling region,
+/// which is the whole screen unless `set_scroll_region` is used to keep
+/// some rows fixed, e.g. for a status line. ANSI escape sequences can
+/// move the write position elsewhere.
+///
+/// Lines scrolling off the top of the screen are kept in a scrollback,
+/// which can be viewed with `scroll_back`.
+///
+/// Each virtual console has its own writer. Only the active console's
+/// writer writes to the screen; the others write to an off-screen buffer.
 pub struct Writer {
+    row_position: usize,
     column_position: usize,
     color_code: ColorCode,
+    /// The colors set with `set_color`, which SGR 0 returns to.
+    base_color: ColorCode,
+    /// First row of the scrolling region.
+    scroll_top: usize,
+    /// Last row of the scrolling region.
+    scroll_bottom: usize,
+    /// Write position saved by an ANSI save cursor sequence.
+    saved_position: (usize, usize),
+    ansi: AnsiParser,
+    scrollback: Scrollback,
     buffer: &'static mut Buffer,
 }
 
 impl Writer {
+    /// Creates a writer with the default colors, writing to `buffer`.
+    fn new(buffer: &'static mut Buffer, scrollback: Scrollback) -> Writer {
+        Writer {
+            row_position: BUFFER_HEIGHT - 1,
+            column_position: 0,
+            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            base_color: ColorCode::new(Color::Yellow, Color::Black),
+            scroll_top: 0,
+            scroll_bottom: BUFFER_HEIGHT - 1,
+            saved_position: (BUFFER_HEIGHT - 1, 0),
+            ansi: AnsiParser::new(),
+            scrollback,
+            buffer,
+        }
+    }
+
     /// Write a single byte to the buffer.
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
-
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
+            b'\r' => self.column_position = 0,
+            // backspace only moves back; erasing is done by overwriting
+            0x08 => self.column_position = self.column_position.saturating_sub(1),
+            byte => self.write_glyph(byte),
         }
     }
 
-    /// Write a whole string to the buffer. Non-ASCII characters
-    /// are converted to a ■ character.
+    /// Write the code page 437 character `byte`, showing its glyph even
+    /// for bytes that are otherwise control characters.
+    fn write_glyph(&mut self, byte: u8) {
+        if self.column_position >= BUFFER_WIDTH {
+            self.new_line();
+        }
+
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.buffer.chars[row][col].write(ScreenChar {
+            ascii_character: byte,
+            color_code,
+        });
+        self.column_position += 1;
+    }
+
+    /// Write a whole string to the buffer, carrying out any ANSI escape
+    /// sequences in it. Characters are shown with their code page 437
+    /// glyph, or as a ■ character if there is none.
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
+        self.show_live();
+        for c in s.chars() {
+            if !c.is_ascii() {
+                // no escape sequence contains one, so it ends any unfinished
+                // sequence rather than being swallowed by it
+                self.ansi.reset();
+                self.write_glyph(cp437::encode_or_unknown(c));
+                continue;
+            }
+            match self.ansi.feed(c as u8) {
+                Action::None => {}
+                Action::Print(byte) => match byte {
+                    // printable ASCII byte, newline, carriage return or backspace
+                    0x20..=0x7e | b'\n' | b'\r' | 0x08 => self.write_byte(byte),
+                    // not part of printable ASCII range
+                    _ => self.write_byte(0xfe),
+                },
+                Action::Csi(csi) => self.apply_csi(&csi),
+                Action::SaveCursor => self.save_position(),
+                Action::RestoreCursor => self.restore_position(),
             }
         }
     }
 
-    /// Add a new line to the bottom of the buffer, moving all other
-    /// lines up.
+    /// Set the colors used for text written from now on.
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+        self.base_color = self.color_code;
+    }
+
+    /// Blank the scrolling region and move to the start of its bottom
+    /// line.
+    pub fn clear(&mut self) {
+        self.show_live();
+        for row in self.scroll_top..=self.scroll_bottom {
+            self.clear_row(row);
+        }
+        self.row_position = self.scroll_bottom;
+        self.column_position = 0;
+        self.update_cursor();
+    }
+
+    /// Move the hardware cursor to where the next character will be
+    /// written.
+    fn update_cursor(&self) {
+        if self.is_active() {
+            cursor::set_position(self.row_position, self.column_position);
+        }
+    }
+
+    /// Move to the start of the next line. At the bottom of the scrolling
+    /// region, all other lines in it are moved up to make room.
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
+        self.column_position = 0;
+        if self.row_position != self.scroll_bottom {
+            self.row_position = (self.row_position + 1).min(BUFFER_HEIGHT - 1);
+            return;
+        }
+        self.keep_top_row();
+        for row in self.scroll_top + 1..=self.scroll_bottom {
             for col in 0..BUFFER_WIDTH {
                 let character = self.buffer.chars[row][col].read();
                 self.buffer.chars[row-1][col].write(character);
             }
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
+        self.clear_row(self.scroll_bottom);
     }
 
     /// Clear a row of the buffer.
@@ -134,18 +294,20 @@ impl Writer {
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_string(s);
+        // once per string rather than per byte, since port I/O is slow
+        self.update_cursor();
         Ok(())
     }
 }
 
 
 lazy_static! {
-    /// Create a static writer we can use for all printing to the screen.
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    /// The writer of the kernel console (virtual console 0), which all
+    /// printing with `print!` goes to. It starts out on the screen.
+    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer::new(
+        unsafe { &mut *(VGA_BUFFER as *mut Buffer) },
+        Scrollback::new(),
+    ));
 }
 
 
@@ -160,15 +322,86 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Like `print!`, but drops the output instead of waiting if the screen is
+/// in use. For interrupt handlers, which would otherwise deadlock when they
+/// interrupt code that is printing. Returns whether the output was printed.
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_try_print(format_args!($($arg)*)));
+}
+
+/// Like `println!`, but drops the output instead of waiting if the screen
+/// is in use. Returns whether the output was printed.
+#[macro_export]
+macro_rules! try_println {
+    () => ($crate::try_print!("\n"));
+    ($($arg:tt)*) => ($crate::try_print!("{}\n", format_args!($($arg)*)));
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
 
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        // in a graphics mode the text buffer is not shown
+        if !framebuffer::print(args) {
+            emergency::lock(&WRITER).write_fmt(args).unwrap();
+        }
     });
 }
 
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    if emergency::is_active() {
+        _print(args);
+        return true;
+    }
+    interrupts::without_interrupts(|| match framebuffer::try_print(args) {
+        Some(true) => true,
+        Some(false) => match WRITER.try_lock() {
+            Some(mut writer) => {
+                writer.write_fmt(args).unwrap();
+                true
+            }
+            None => false,
+        },
+        None => false,
+    })
+}
+
+/// Move the blinking cursor to the given row and column. It moves back
+/// to the write position the next time something is printed.
+pub fn set_cursor_position(row: usize, col: usize) {
+    interrupts::without_interrupts(|| cursor::set_position(row, col));
+}
+
+/// Returns the row and column of the blinking cursor.
+pub fn cursor_position() -> (usize, usize) {
+    interrupts::without_interrupts(cursor::position)
+}
+
+/// Change the shape of the blinking cursor.
+pub fn set_cursor_shape(shape: CursorShape) {
+    interrupts::without_interrupts(|| cursor::set_shape(shape));
+}
+
+/// Show or hide the blinking cursor.
+pub fn set_cursor_visible(visible: bool) {
+    interrupts::without_interrupts(|| cursor::set_visible(visible));
+}
+
+/// Blank the scrolling region (the whole screen by default).
+pub fn clear_screen() {
+    interrupts::without_interrupts(|| WRITER.lock().clear());
+}
+
+/// Set the colors used for text printed from now on.
+pub fn set_color(foreground: Color, background: Color) {
+    interrupts::without_interrupts(|| WRITER.lock().set_color(foreground, background));
+}
+
 
 // TESTS -----------------------------------------------------------------------
 
@@ -184,6 +417,15 @@ fn test_println_many() {
     }
 }
 
+#[test_case]
+fn test_try_print_when_busy() {
+    interrupts::without_interrupts(|| {
+        let _writer = WRITER.lock();
+        assert!(!try_println!("test_try_print_when_busy output"));
+    });
+    assert!(try_println!("test_try_print_when_busy output"));
+}
+
 #[test_case]
 fn test_println_output() {
     use core::fmt::Write;
@@ -200,4 +442,29 @@ fn test_println_output() {
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_cursor_follows_output() {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nabc").expect("write failed");
+        assert_eq!(cursor::position(), (BUFFER_HEIGHT - 1, 3));
+    });
+}
+
+#[test_case]
+fn test_unicode_output() {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "\n25°C é ♪→€").expect("writeln failed");
+        let row: [u8; 10] = [b'2', b'5', 0xf8, b'C', b' ', 0x82, b' ', 0x0d, 0x1a, 0xfe];
+        for (i, &byte) in row.iter().enumerate() {
+            assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 2][i].read().ascii_character, byte);
+        }
+    });
+}
@@ -0,0 +1,315 @@
+use super::{cursor, Color, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// Most numeric parameters kept for one control sequence; any more are
+/// ignored.
+const MAX_PARAMS: usize = 4;
+
+const ESCAPE: u8 = 0x1b;
+
+/// What the writer should do for the bytes fed to `AnsiParser` so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Action {
+    /// Nothing yet; the byte is part of an escape sequence.
+    None,
+    /// Write the byte (or carry out the control character).
+    Print(u8),
+    /// A control sequence (`ESC [ ... final`) is complete.
+    Csi(Csi),
+    /// `ESC 7`: save the cursor position.
+    SaveCursor,
+    /// `ESC 8`: restore the cursor position.
+    RestoreCursor,
+}
+
+/// A complete control sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Csi {
+    params: [u16; MAX_PARAMS],
+    count: usize,
+    /// Whether the parameters started with '?' (a DEC private sequence).
+    pub private: bool,
+    /// The byte ending the sequence, which selects the function.
+    pub function: u8,
+}
+
+impl Csi {
+    /// Returns the parameter at `index`, or `default` if it was left out
+    /// or is 0.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+
+    /// Returns the parameters given, where an empty parameter is 0.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.count]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After ESC.
+    Escape,
+    /// After ESC [.
+    Csi,
+}
+
+/// Splits a stream of bytes into printable characters and VT100/ANSI
+/// escape sequences.
+pub(super) struct AnsiParser {
+    state: State,
+    csi: Csi,
+}
+
+impl AnsiParser {
+    pub const fn new() -> Self {
+        AnsiParser {
+            state: State::Ground,
+            csi: Csi { params: [0; MAX_PARAMS], count: 0, private: false, function: 0 },
+        }
+    }
+
+    /// Abandon any unfinished escape sequence.
+    pub fn reset(&mut self) {
+        self.state = State::Ground;
+    }
+
+    /// Feed one byte, returning what to do once it completes something.
+    pub fn feed(&mut self, byte: u8) -> Action {
+        match self.state {
+            State::Ground if byte == ESCAPE => {
+                self.state = State::Escape;
+                Action::None
+            }
+            State::Ground => Action::Print(byte),
+            State::Escape => {
+                self.state = State::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = State::Csi;
+                        self.csi = Csi { params: [0; MAX_PARAMS], count: 0, private: false, function: 0 };
+                        Action::None
+                    }
+                    b'7' => Action::SaveCursor,
+                    b'8' => Action::RestoreCursor,
+                    // unsupported escape; drop it
+                    _ => Action::None,
+                }
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.csi.count == 0 {
+                        self.csi.count = 1;
+                    }
+                    if let Some(param) = self.csi.params.get_mut(self.csi.count - 1) {
+                        *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    }
+                    Action::None
+                }
+                b';' => {
+                    // an empty first parameter still counts
+                    self.csi.count = (self.csi.count.max(1) + 1).min(MAX_PARAMS + 1);
+                    Action::None
+                }
+                b'?' => {
+                    self.csi.private = true;
+                    Action::None
+                }
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    self.csi.count = self.csi.count.min(MAX_PARAMS);
+                    self.csi.function = byte;
+                    Action::Csi(self.csi)
+                }
+                // intermediate bytes, which no sequence we support uses
+                _ => Action::None,
+            },
+        }
+    }
+}
+
+/// The VGA colors for the 8 ANSI colors, in ANSI order.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black, Color::Red, Color::Green, Color::Brown,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray,
+];
+/// The VGA colors for the 8 bright ANSI colors, in ANSI order.
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+    Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+/// Bit of the foreground color that selects its bright variant.
+const BRIGHT: u8 = 0x08;
+
+impl Writer {
+    /// Carry out a control sequence.
+    pub(super) fn apply_csi(&mut self, csi: &Csi) {
+        let n = csi.param(0, 1) as usize;
+        match (csi.private, csi.function) {
+            // cursor movement
+            (false, b'A') => self.move_to(self.row_position.saturating_sub(n), self.column_position),
+            (false, b'B') => self.move_to(self.row_position + n, self.column_position),
+            (false, b'C') => self.move_to(self.row_position, self.column_position + n),
+            (false, b'D') => self.move_to(self.row_position, self.column_position.saturating_sub(n)),
+            (false, b'G') => self.move_to(self.row_position, n - 1),
+            (false, b'H') | (false, b'f') => {
+                let col = csi.param(1, 1) as usize;
+                self.move_to(n - 1, col - 1);
+            }
+            // erasing
+            (false, b'J') => self.erase_screen(csi.param(0, 0)),
+            (false, b'K') => self.erase_line(csi.param(0, 0)),
+            (false, b'm') => self.select_graphic_rendition(csi.params()),
+            (false, b's') => self.save_position(),
+            (false, b'u') => self.restore_position(),
+            // show and hide the cursor
+            (true, b'h') if csi.param(0, 0) == 25 && self.is_active() => cursor::set_visible(true),
+            (true, b'l') if csi.param(0, 0) == 25 && self.is_active() => cursor::set_visible(false),
+            _ => {}
+        }
+    }
+
+    /// Remember the write position, for `restore_position`.
+    pub(super) fn save_position(&mut self) {
+        self.saved_position = (self.row_position, self.column_position);
+    }
+
+    /// Go back to the write position saved with `save_position`.
+    pub(super) fn restore_position(&mut self) {
+        let (row, col) = self.saved_position;
+        self.move_to(row, col);
+    }
+
+    /// Move the write position, keeping it on the screen.
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+    }
+
+    /// Blank the cells of `row` from `start` up to (not including) `end`.
+    fn erase(&mut self, row: usize, start: usize, end: usize) {
+        let blank = ScreenChar { ascii_character: b' ', color_code: self.color_code };
+        for col in start..end.min(BUFFER_WIDTH) {
+            self.buffer.chars[row][col].write(blank);
+        }
+    }
+
+    /// Erase part of the current line: to its end (0), to its start (1)
+    /// or all of it (2).
+    fn erase_line(&mut self, mode: u16) {
+        let (row, col) = (self.row_position, self.column_position);
+        match mode {
+            0 => self.erase(row, col, BUFFER_WIDTH),
+            1 => self.erase(row, 0, col + 1),
+            2 => self.erase(row, 0, BUFFER_WIDTH),
+            _ => {}
+        }
+    }
+
+    /// Erase part of the screen: from the write position to the end (0),
+    /// from the start to the write position (1) or all of it (2).
+    fn erase_screen(&mut self, mode: u16) {
+        let row = self.row_position;
+        let (rows, line_mode) = match mode {
+            0 => (row + 1..BUFFER_HEIGHT, 0),
+            1 => (0..row, 1),
+            2 => (0..BUFFER_HEIGHT, 2),
+            _ => return,
+        };
+        for other_row in rows {
+            self.erase(other_row, 0, BUFFER_WIDTH);
+        }
+        self.erase_line(line_mode);
+    }
+
+    /// Change the colors as given by the parameters of an SGR sequence.
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let (mut foreground, mut background) = (self.color_code.0 & 0x0f, self.color_code.0 >> 4);
+        let (base_foreground, base_background) = (self.base_color.0 & 0x0f, self.base_color.0 >> 4);
+        // no parameters means reset
+        for &param in if params.is_empty() { &[0][..] } else { params } {
+            match param {
+                0 => {
+                    foreground = base_foreground;
+                    background = base_background;
+                }
+                1 => foreground |= BRIGHT,
+                22 => foreground &= !BRIGHT,
+                7 => core::mem::swap(&mut foreground, &mut background),
+                30..=37 => foreground = ANSI_COLORS[param as usize - 30] as u8 | foreground & BRIGHT,
+                39 => foreground = base_foreground,
+                40..=47 => background = ANSI_COLORS[param as usize - 40] as u8,
+                49 => background = base_background,
+                90..=97 => foreground = ANSI_BRIGHT_COLORS[param as usize - 90] as u8,
+                100..=107 => background = ANSI_BRIGHT_COLORS[param as usize - 100] as u8,
+                _ => {}
+            }
+        }
+        // the top bit of the background makes text blink on most cards
+        self.color_code = ColorCode((background & 0x07) << 4 | foreground & 0x0f);
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_parse_sequences() {
+    let mut parser = AnsiParser::new();
+    let mut last = Action::None;
+    for &byte in b"\x1b[1;31m" {
+        last = parser.feed(byte);
+    }
+    match last {
+        Action::Csi(csi) => {
+            assert_eq!(csi.function, b'm');
+            assert_eq!(csi.params(), &[1, 31]);
+        }
+        action => panic!("unexpected {:?}", action),
+    }
+
+    assert_eq!(parser.feed(b'x'), Action::Print(b'x'));
+    for &byte in b"\x1b[?25" {
+        assert_eq!(parser.feed(byte), Action::None);
+    }
+    match parser.feed(b'l') {
+        Action::Csi(csi) => assert!(csi.private && csi.param(0, 1) == 25),
+        action => panic!("unexpected {:?}", action),
+    }
+
+    for &byte in b"\x1b[3" {
+        parser.feed(byte);
+    }
+    parser.reset();
+    assert_eq!(parser.feed(b'm'), Action::Print(b'm'));
+}
+
+#[test_case]
+fn test_writer_escape_sequences() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = super::WRITER.lock();
+        let saved = writer.save();
+
+        write!(writer, "\x1b[3;5H\x1b[31;44mA\x1b[0mB\x1b[2DC").expect("write failed");
+        let red_on_blue = ColorCode::new(Color::Red, Color::Blue);
+        assert_eq!(writer.buffer.chars[2][4].read(),
+            ScreenChar { ascii_character: b'C', color_code: writer.base_color });
+        assert_eq!(writer.buffer.chars[2][5].read(),
+            ScreenChar { ascii_character: b'B', color_code: writer.base_color });
+
+        write!(writer, "\x1b[31;44mX\x1b[1K").expect("write failed");
+        assert_eq!(writer.buffer.chars[2][5].read(),
+            ScreenChar { ascii_character: b' ', color_code: red_on_blue });
+        assert_eq!(cursor::position(), (2, 6));
+
+        write!(writer, "\x1b[0m").expect("write failed");
+        writer.restore(&saved);
+    });
+}
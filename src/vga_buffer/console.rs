@@ -0,0 +1,143 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::scrollback::Scrollback;
+use super::{
+    Buffer, Color, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, VGA_BUFFER, WRITER,
+};
+
+/// Number of virtual consoles, switched between with Alt+F1 to Alt+F6.
+/// Console 0 is the kernel console, which `print!` writes to.
+pub const CONSOLE_COUNT: usize = 6;
+
+type Cells = [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT];
+
+const BLANK_CELLS: Cells = [[ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode((Color::Black as u8) << 4 | Color::Yellow as u8),
+}; BUFFER_WIDTH]; BUFFER_HEIGHT];
+
+/// Off-screen buffers for the consoles that are not on the screen. They
+/// are handed between consoles as the active console changes, so which
+/// console uses which buffer varies.
+static mut OFF_SCREEN: [Cells; CONSOLE_COUNT - 1] = [BLANK_CELLS; CONSOLE_COUNT - 1];
+
+/// Index of the console on the screen.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates the writer of a console that starts out off the screen, using
+/// off-screen buffer `index`. Must be called only once for each buffer.
+fn off_screen_writer(index: usize) -> Mutex<Writer> {
+    let buffer = unsafe { &mut *(&mut OFF_SCREEN[index] as *mut Cells as *mut Buffer) };
+    Mutex::new(Writer::new(buffer, Scrollback::empty()))
+}
+
+lazy_static! {
+    /// The writers of consoles 1 and up.
+    static ref OTHER_CONSOLES: [Mutex<Writer>; CONSOLE_COUNT - 1] = [
+        off_screen_writer(0),
+        off_screen_writer(1),
+        off_screen_writer(2),
+        off_screen_writer(3),
+        off_screen_writer(4),
+    ];
+}
+
+impl Writer {
+    /// Returns whether this writer's console is the one on the screen.
+    pub fn is_active(&self) -> bool {
+        self.buffer as *const Buffer as usize == VGA_BUFFER
+    }
+
+    /// Put the console of `other` on the screen in place of this writer's
+    /// console, which must be the active one. The screen contents are
+    /// swapped with `other`'s off-screen buffer, which this writer then
+    /// uses.
+    fn hand_over_screen(&mut self, other: &mut Writer) {
+        self.show_live();
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let shown = self.buffer.chars[row][col].read();
+                let hidden = other.buffer.chars[row][col].read();
+                self.buffer.chars[row][col].write(hidden);
+                other.buffer.chars[row][col].write(shown);
+            }
+        }
+        core::mem::swap(&mut self.buffer, &mut other.buffer);
+        other.update_cursor();
+    }
+}
+
+
+/// Returns the writer of console `index`.
+///
+/// Panics if `index` is not below `CONSOLE_COUNT`.
+pub fn console(index: usize) -> &'static Mutex<Writer> {
+    match index {
+        0 => &WRITER,
+        _ => &OTHER_CONSOLES[index - 1],
+    }
+}
+
+/// Run `f` with the writer of console `index` locked.
+pub fn with_console<R>(index: usize, f: impl FnOnce(&mut Writer) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut console(index).lock()))
+}
+
+/// Returns the index of the console on the screen.
+pub fn active_console() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Put console `index` on the screen. Indexes of consoles that do not
+/// exist are ignored.
+pub fn switch_console(index: usize) {
+    if index >= CONSOLE_COUNT {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let active = active_console();
+        if index == active {
+            return;
+        }
+        // no other code holds two writers at once, so the order of
+        // locking does not matter
+        let mut from = console(active).lock();
+        let mut to = console(index).lock();
+        from.hand_over_screen(&mut to);
+        ACTIVE.store(index, Ordering::Relaxed);
+    });
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_switch_console() {
+    use core::fmt::Write;
+
+    let on_screen = || unsafe {
+        (*(VGA_BUFFER as *const Buffer)).chars[BUFFER_HEIGHT - 1][0].read().ascii_character
+    };
+
+    interrupts::without_interrupts(|| {
+        with_console(0, |writer| write!(writer, "\nfirst").expect("write failed"));
+        with_console(1, |writer| write!(writer, "\nsecond").expect("write failed"));
+        // output to a console off the screen leaves the screen alone
+        assert_eq!(on_screen(), b'f');
+
+        switch_console(1);
+        assert_eq!(active_console(), 1);
+        assert_eq!(on_screen(), b's');
+        with_console(0, |writer| {
+            assert!(!writer.is_active());
+            assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 1][0].read().ascii_character, b'f');
+        });
+
+        switch_console(0);
+        assert_eq!(on_screen(), b'f');
+    });
+}
@@ -0,0 +1,82 @@
+/// The byte the VGA text buffer shows as ■, used for characters code page
+/// 437 cannot represent.
+pub const UNKNOWN: u8 = 0xfe;
+
+/// The glyphs of bytes 0x01 to 0x1f, which the VGA text buffer shows as
+/// symbols rather than treating them as control characters.
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// The glyph of byte 0x7f.
+const HOUSE: char = '⌂';
+
+/// The glyphs of bytes 0x80 to 0xff.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Characters that look like a glyph in code page 437 without being the
+/// code point it is usually mapped to.
+const ALIASES: [(char, u8); 6] = [
+    ('β', 0xe1),
+    ('μ', 0xe6),
+    ('∑', 0xe4),
+    ('\u{2126}', 0xea), // OHM SIGN
+    ('∈', 0xee),
+    ('∅', 0xed),
+];
+
+/// Returns the code page 437 byte showing `c`, if there is one. ASCII
+/// control characters have no glyph and give `None`.
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        HOUSE => Some(0x7f),
+        _ if (c as u32) < 0x80 => None,
+        _ => LOW.iter().position(|&glyph| glyph == c).map(|i| i as u8 + 0x01)
+            .or_else(|| HIGH.iter().position(|&glyph| glyph == c).map(|i| i as u8 + 0x80))
+            .or_else(|| ALIASES.iter().find(|&&(alias, _)| alias == c).map(|&(_, byte)| byte)),
+    }
+}
+
+/// Like `encode`, but returns ■ for characters that cannot be shown.
+pub fn encode_or_unknown(c: char) -> u8 {
+    encode(c).unwrap_or(UNKNOWN)
+}
+
+/// Returns the character shown for the code page 437 byte `byte`. Byte 0
+/// is shown as a blank and returned as a space.
+pub fn decode(byte: u8) -> char {
+    match byte {
+        0x00 => ' ',
+        0x01..=0x1f => LOW[byte as usize - 0x01],
+        0x7f => HOUSE,
+        0x80..=0xff => HIGH[byte as usize - 0x80],
+        _ => byte as char,
+    }
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_cp437_round_trip() {
+    for byte in 1..=0xffu8 {
+        assert_eq!(encode(decode(byte)), Some(byte));
+    }
+    assert_eq!(encode('é'), Some(0x82));
+    assert_eq!(encode('°'), Some(0xf8));
+    assert_eq!(encode('┼'), Some(0xc5));
+    assert_eq!(encode('\u{2126}'), Some(0xea));
+    assert_eq!(encode('\n'), None);
+    assert_eq!(encode_or_unknown('€'), UNKNOWN);
+}
@@ -0,0 +1,110 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::{BUFFER_HEIGHT, BUFFER_WIDTH};
+use crate::emergency;
+
+// CRT controller registers.
+const CURSOR_START: u8 = 0x0a;
+const CURSOR_END: u8 = 0x0b;
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+/// Cursor start register flag: hide the cursor.
+const CURSOR_DISABLED: u8 = 1 << 5;
+/// The bits of the cursor start and end registers holding the scanline.
+const SCANLINE_MASK: u8 = 0x1f;
+
+/// The shape of the blinking text cursor, given by the scanlines of the
+/// 16-scanline character cell it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    /// The bottom two scanlines, the BIOS default.
+    Underline,
+    /// The bottom half of the cell.
+    HalfBlock,
+    /// The whole cell.
+    Block,
+    /// From the `start` to the `end` scanline (0-15), inclusive.
+    Custom { start: u8, end: u8 },
+}
+
+impl CursorShape {
+    /// Returns the first and last scanline covered by the cursor.
+    fn scanlines(self) -> (u8, u8) {
+        match self {
+            CursorShape::Underline => (14, 15),
+            CursorShape::HalfBlock => (8, 15),
+            CursorShape::Block => (0, 15),
+            CursorShape::Custom { start, end } => (start & SCANLINE_MASK, end & SCANLINE_MASK),
+        }
+    }
+}
+
+/// The index and data ports of the VGA CRT controller.
+struct Crtc {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Crtc {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register);
+            self.data.write(value);
+        }
+    }
+}
+
+static CRTC: Mutex<Crtc> = Mutex::new(Crtc {
+    index: Port::new(0x3d4),
+    data: Port::new(0x3d5),
+});
+
+
+/// Move the cursor to the given cell. Positions off the screen are
+/// clamped to its edges.
+pub(super) fn set_position(row: usize, col: usize) {
+    let position = row.min(BUFFER_HEIGHT - 1) * BUFFER_WIDTH + col.min(BUFFER_WIDTH - 1);
+    let mut crtc = emergency::lock(&CRTC);
+    crtc.write(CURSOR_LOCATION_LOW, position as u8);
+    crtc.write(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+}
+
+/// Returns the cell the cursor is in, as (row, column).
+pub(super) fn position() -> (usize, usize) {
+    let mut crtc = emergency::lock(&CRTC);
+    let position = (crtc.read(CURSOR_LOCATION_HIGH) as usize) << 8
+        | crtc.read(CURSOR_LOCATION_LOW) as usize;
+    (position / BUFFER_WIDTH, position % BUFFER_WIDTH)
+}
+
+/// Change the shape of the cursor, keeping it hidden if it is.
+pub(super) fn set_shape(shape: CursorShape) {
+    let (start, end) = shape.scanlines();
+    let mut crtc = emergency::lock(&CRTC);
+    let flags = crtc.read(CURSOR_START) & !SCANLINE_MASK;
+    crtc.write(CURSOR_START, flags | start);
+    let flags = crtc.read(CURSOR_END) & !SCANLINE_MASK;
+    crtc.write(CURSOR_END, flags | end);
+}
+
+/// Show or hide the cursor.
+pub(super) fn set_visible(visible: bool) {
+    let mut crtc = emergency::lock(&CRTC);
+    let start = crtc.read(CURSOR_START);
+    let start = if visible { start & !CURSOR_DISABLED } else { start | CURSOR_DISABLED };
+    crtc.write(CURSOR_START, start);
+}
+
+/// Returns whether the cursor is shown.
+pub(super) fn is_visible() -> bool {
+    emergency::lock(&CRTC).read(CURSOR_START) & CURSOR_DISABLED == 0
+}
@@ -0,0 +1,252 @@
+use x86_64::instructions::interrupts;
+
+use super::{cp437, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+
+/// A rectangle of character cells. Parts of it off the screen are
+/// ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub row: usize,
+    pub col: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Rect {
+    pub fn new(row: usize, col: usize, height: usize, width: usize) -> Rect {
+        Rect { row, col, height, width }
+    }
+
+    /// Returns the rows covered by the rectangle, clipped to the screen.
+    fn rows(&self) -> core::ops::Range<usize> {
+        self.row.min(BUFFER_HEIGHT)..(self.row + self.height).min(BUFFER_HEIGHT)
+    }
+
+    /// Returns the columns covered by the rectangle, clipped to the screen.
+    fn cols(&self) -> core::ops::Range<usize> {
+        self.col.min(BUFFER_WIDTH)..(self.col + self.width).min(BUFFER_WIDTH)
+    }
+}
+
+/// The line characters used to draw a box, from code page 437.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxStyle {
+    Single,
+    Double,
+}
+
+impl BoxStyle {
+    /// Returns the characters for the top left, top right, bottom left and
+    /// bottom right corners, and the horizontal and vertical lines.
+    fn characters(self) -> [u8; 6] {
+        match self {
+            BoxStyle::Single => [0xda, 0xbf, 0xc0, 0xd9, 0xc4, 0xb3],
+            BoxStyle::Double => [0xc9, 0xbb, 0xc8, 0xbc, 0xcd, 0xba],
+        }
+    }
+}
+
+/// A copy of the screen contents and the writer's state, taken with
+/// `save_screen`.
+pub struct Screen {
+    chars: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    row_position: usize,
+    column_position: usize,
+    color_code: ColorCode,
+    scroll_top: usize,
+    scroll_bottom: usize,
+}
+
+impl Writer {
+    /// Write `s` starting at the given cell, without wrapping, scrolling
+    /// or moving the write position. Characters are shown with their code
+    /// page 437 glyph, or as a ■ character if there is none.
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str, color: ColorCode) {
+        self.show_live();
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        for (col, c) in (col..BUFFER_WIDTH).zip(s.chars()) {
+            let byte = cp437::encode_or_unknown(c);
+            self.buffer.chars[row][col].write(ScreenChar {
+                ascii_character: byte,
+                color_code: color,
+            });
+        }
+    }
+
+    /// Fill a rectangle with the character `byte` (in code page 437).
+    pub fn fill_rect(&mut self, rect: Rect, byte: u8, color: ColorCode) {
+        self.show_live();
+        let blank = ScreenChar { ascii_character: byte, color_code: color };
+        for row in rect.rows() {
+            for col in rect.cols() {
+                self.buffer.chars[row][col].write(blank);
+            }
+        }
+    }
+
+    /// Draw the outline of a rectangle with line characters, leaving its
+    /// inside untouched. Rectangles smaller than 2x2 are not drawn.
+    pub fn draw_box(&mut self, rect: Rect, style: BoxStyle, color: ColorCode) {
+        if rect.height < 2 || rect.width < 2 {
+            return;
+        }
+        self.show_live();
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] =
+            style.characters();
+        let (top, bottom) = (rect.row, rect.row + rect.height - 1);
+        let (left, right) = (rect.col, rect.col + rect.width - 1);
+
+        let mut put = |row: usize, col: usize, byte: u8| {
+            if row < BUFFER_HEIGHT && col < BUFFER_WIDTH {
+                self.buffer.chars[row][col].write(ScreenChar {
+                    ascii_character: byte,
+                    color_code: color,
+                });
+            }
+        };
+        for col in left + 1..right {
+            put(top, col, horizontal);
+            put(bottom, col, horizontal);
+        }
+        for row in top + 1..bottom {
+            put(row, left, vertical);
+            put(row, right, vertical);
+        }
+        put(top, left, top_left);
+        put(top, right, top_right);
+        put(bottom, left, bottom_left);
+        put(bottom, right, bottom_right);
+    }
+
+    /// Restrict scrolling to the rows from `top` to `bottom` (inclusive);
+    /// rows outside the region stay where they are. Text continues at the
+    /// start of the region's bottom row.
+    pub fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        self.show_live();
+        let bottom = bottom.min(BUFFER_HEIGHT - 1);
+        self.scroll_top = top.min(bottom);
+        self.scroll_bottom = bottom;
+        self.row_position = bottom;
+        self.column_position = 0;
+        self.update_cursor();
+    }
+
+    /// Returns a copy of the screen contents and the writer's state,
+    /// going back to the live screen first.
+    pub fn save(&mut self) -> Screen {
+        self.show_live();
+        let blank = ScreenChar { ascii_character: b' ', color_code: self.color_code };
+        let mut chars = [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, saved) in chars.iter_mut().enumerate() {
+            for (col, saved) in saved.iter_mut().enumerate() {
+                *saved = self.buffer.chars[row][col].read();
+            }
+        }
+        Screen {
+            chars,
+            row_position: self.row_position,
+            column_position: self.column_position,
+            color_code: self.color_code,
+            scroll_top: self.scroll_top,
+            scroll_bottom: self.scroll_bottom,
+        }
+    }
+
+    /// Put back the screen contents and writer state saved with `save`.
+    pub fn restore(&mut self, screen: &Screen) {
+        self.show_live();
+        for (row, saved) in screen.chars.iter().enumerate() {
+            for (col, &saved) in saved.iter().enumerate() {
+                self.buffer.chars[row][col].write(saved);
+            }
+        }
+        self.row_position = screen.row_position;
+        self.column_position = screen.column_position;
+        self.color_code = screen.color_code;
+        self.scroll_top = screen.scroll_top;
+        self.scroll_bottom = screen.scroll_bottom;
+        self.update_cursor();
+    }
+}
+
+
+/// Write `s` at the given cell without moving the write position. See
+/// `Writer::write_at`.
+pub fn write_at(row: usize, col: usize, s: &str, color: ColorCode) {
+    interrupts::without_interrupts(|| WRITER.lock().write_at(row, col, s, color));
+}
+
+/// Fill a rectangle with the character `byte` (in code page 437).
+pub fn fill_rect(rect: Rect, byte: u8, color: ColorCode) {
+    interrupts::without_interrupts(|| WRITER.lock().fill_rect(rect, byte, color));
+}
+
+/// Draw the outline of a rectangle with line characters.
+pub fn draw_box(rect: Rect, style: BoxStyle, color: ColorCode) {
+    interrupts::without_interrupts(|| WRITER.lock().draw_box(rect, style, color));
+}
+
+/// Restrict scrolling to the rows from `top` to `bottom` (inclusive).
+pub fn set_scroll_region(top: usize, bottom: usize) {
+    interrupts::without_interrupts(|| WRITER.lock().set_scroll_region(top, bottom));
+}
+
+/// Returns a copy of the screen, to be put back with `restore_screen`.
+pub fn save_screen() -> Screen {
+    interrupts::without_interrupts(|| WRITER.lock().save())
+}
+
+/// Put back a screen saved with `save_screen`.
+pub fn restore_screen(screen: &Screen) {
+    interrupts::without_interrupts(|| WRITER.lock().restore(screen));
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_positioned_writes() {
+    use super::Color;
+
+    let color = ColorCode::new(Color::White, Color::Blue);
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let saved = writer.save();
+
+        writer.draw_box(Rect::new(2, 10, 3, 6), BoxStyle::Single, color);
+        writer.write_at(3, 11, "hi", color);
+        assert_eq!(writer.buffer.chars[2][10].read().ascii_character, 0xda);
+        assert_eq!(writer.buffer.chars[4][15].read().ascii_character, 0xd9);
+        assert_eq!(writer.buffer.chars[3][12].read(),
+            ScreenChar { ascii_character: b'i', color_code: color });
+
+        writer.restore(&saved);
+        assert_eq!(writer.buffer.chars[2][10].read(), saved.chars[2][10]);
+    });
+}
+
+#[test_case]
+fn test_scroll_region_keeps_status_line() {
+    use core::fmt::Write;
+    use super::Color;
+
+    let color = ColorCode::new(Color::Black, Color::LightGray);
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let saved = writer.save();
+
+        writer.set_scroll_region(0, BUFFER_HEIGHT - 2);
+        writer.write_at(BUFFER_HEIGHT - 1, 0, "Status", color);
+        for _ in 0..BUFFER_HEIGHT {
+            writeln!(writer, "scrolling").expect("writeln failed");
+        }
+        // the last line written is just above the (blank) write row
+        assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 3][0].read().ascii_character, b's');
+        assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 2][0].read().ascii_character, b' ');
+        assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 1][0].read().ascii_character, b'S');
+
+        writer.restore(&saved);
+    });
+}
@@ -0,0 +1,288 @@
+use alloc::vec::Vec;
+use core::mem;
+
+use super::{
+    active_console, cursor, with_console, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT,
+    BUFFER_WIDTH, CONSOLE_COUNT,
+};
+use crate::allocator;
+use crate::cmdline;
+use crate::warn;
+
+/// Lines kept before the heap is available.
+const BOOT_LINES: usize = 50;
+/// Lines kept once the heap is available, unless the `scrollback=` option
+/// on the kernel command line asks for a different number.
+pub const DEFAULT_LINES: usize = 200;
+/// Most lines kept per console, so that the scrollback of all consoles
+/// together takes up at most half of the heap.
+pub const MAX_LINES: usize =
+    allocator::HEAP_SIZE / 2 / CONSOLE_COUNT / mem::size_of::<Line>();
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
+const BLANK_LINE: Line = [ScreenChar { ascii_character: b' ', color_code: ColorCode(0) }; BUFFER_WIDTH];
+
+/// Where the lines of the scrollback are kept.
+enum Storage {
+    /// A small static buffer, used until the heap is available.
+    Boot([Line; BOOT_LINES]),
+    Heap(Vec<Line>),
+}
+
+/// The lines that scrolled off the top of the screen, kept in a ring
+/// buffer, and the state of viewing them.
+pub(super) struct Scrollback {
+    storage: Storage,
+    /// Index of the oldest line.
+    start: usize,
+    len: usize,
+    /// How many lines the view is scrolled back; 0 shows the live screen.
+    offset: usize,
+    /// The live screen, kept while the view is scrolled back.
+    live: [Line; BUFFER_HEIGHT],
+    /// Whether the cursor was visible before the view was scrolled back.
+    cursor_visible: bool,
+}
+
+impl Scrollback {
+    pub const fn new() -> Self {
+        Scrollback {
+            storage: Storage::Boot([BLANK_LINE; BOOT_LINES]),
+            start: 0,
+            len: 0,
+            offset: 0,
+            live: [BLANK_LINE; BUFFER_HEIGHT],
+            cursor_visible: true,
+        }
+    }
+
+    /// Creates a scrollback without the static buffer, which keeps no
+    /// lines until the heap is available.
+    pub const fn empty() -> Self {
+        Scrollback {
+            storage: Storage::Heap(Vec::new()),
+            start: 0,
+            len: 0,
+            offset: 0,
+            live: [BLANK_LINE; BUFFER_HEIGHT],
+            cursor_visible: true,
+        }
+    }
+
+    fn lines(&self) -> &[Line] {
+        match &self.storage {
+            Storage::Boot(lines) => lines,
+            Storage::Heap(lines) => lines,
+        }
+    }
+
+    fn lines_mut(&mut self) -> &mut [Line] {
+        match &mut self.storage {
+            Storage::Boot(lines) => lines,
+            Storage::Heap(lines) => lines,
+        }
+    }
+
+    /// Add a line, dropping the oldest one if the scrollback is full.
+    fn push(&mut self, line: Line) {
+        let capacity = self.lines().len();
+        if capacity == 0 {
+            return;
+        }
+        let index = (self.start + self.len) % capacity;
+        self.lines_mut()[index] = line;
+        if self.len < capacity {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % capacity;
+        }
+    }
+
+    /// Returns the `n`th newest line, where 1 is the newest.
+    fn from_end(&self, n: usize) -> &Line {
+        let lines = self.lines();
+        &lines[(self.start + self.len - n) % lines.len()]
+    }
+
+    /// Move the lines to `heap`, an empty vector with room for `lines`
+    /// lines. If there are more than that, the newest ones are kept.
+    /// Returns the old storage.
+    fn replace_storage(&mut self, mut heap: Vec<Line>, lines: usize) -> Storage {
+        let kept = self.len.min(lines);
+        for n in (1..=kept).rev() {
+            heap.push(*self.from_end(n));
+        }
+        heap.resize(lines, BLANK_LINE);
+        self.start = 0;
+        self.len = kept;
+        mem::replace(&mut self.storage, Storage::Heap(heap))
+    }
+}
+
+impl Writer {
+    /// Keep the top row of the screen in the scrollback, before it is
+    /// scrolled away. Rows kept fixed above a scrolling region are not
+    /// kept, since they never scroll.
+    pub(super) fn keep_top_row(&mut self) {
+        if self.scroll_top != 0 {
+            return;
+        }
+        let mut line = BLANK_LINE;
+        for (col, character) in line.iter_mut().enumerate() {
+            *character = self.buffer.chars[0][col].read();
+        }
+        self.scrollback.push(line);
+    }
+
+    /// Returns whether the screen shows older lines instead of the latest
+    /// output.
+    pub fn is_scrolled_back(&self) -> bool {
+        self.scrollback.offset != 0
+    }
+
+    /// Show `lines` older lines, as far back as the scrollback goes. Only
+    /// the active console can be scrolled back.
+    pub fn scroll_back(&mut self, lines: usize) {
+        if !self.is_active() {
+            return;
+        }
+        let offset = (self.scrollback.offset + lines).min(self.scrollback.len);
+        self.set_view_offset(offset);
+    }
+
+    /// Show `lines` newer lines, up to the live screen.
+    pub fn scroll_forward(&mut self, lines: usize) {
+        self.set_view_offset(self.scrollback.offset.saturating_sub(lines));
+    }
+
+    /// Go back to showing the latest output. Everything that changes the
+    /// screen does this first.
+    pub fn show_live(&mut self) {
+        self.set_view_offset(0);
+    }
+
+    /// Keep up to `lines` lines of scrollback in `heap`, an empty vector
+    /// with room for them. Returns the old storage, to be freed once the
+    /// writer is unlocked.
+    fn replace_scrollback(&mut self, heap: Vec<Line>, lines: usize) -> Storage {
+        self.show_live();
+        self.scrollback.replace_storage(heap, lines)
+    }
+
+    /// Redraw the screen scrolled back by `offset` lines.
+    fn set_view_offset(&mut self, offset: usize) {
+        let old_offset = self.scrollback.offset;
+        if offset == old_offset {
+            return;
+        }
+        if old_offset == 0 {
+            for (row, live) in self.scrollback.live.iter_mut().enumerate() {
+                for (col, character) in live.iter_mut().enumerate() {
+                    *character = self.buffer.chars[row][col].read();
+                }
+            }
+            self.scrollback.cursor_visible = cursor::is_visible();
+            cursor::set_visible(false);
+        }
+
+        self.scrollback.offset = offset;
+        for row in 0..BUFFER_HEIGHT {
+            let line = if row < offset {
+                *self.scrollback.from_end(offset - row)
+            } else {
+                self.scrollback.live[row - offset]
+            };
+            for (col, &character) in line.iter().enumerate() {
+                self.buffer.chars[row][col].write(character);
+            }
+        }
+
+        if offset == 0 {
+            cursor::set_visible(self.scrollback.cursor_visible);
+        }
+    }
+}
+
+
+/// Move the scrollback to the heap, keeping as many lines as the
+/// `scrollback=` option on the kernel command line gives, or
+/// `DEFAULT_LINES`, for every virtual console. Call once the heap is
+/// initialized.
+pub fn init_scrollback() {
+    let lines = cmdline::value("scrollback")
+        .and_then(|lines| lines.parse().ok())
+        .unwrap_or(DEFAULT_LINES);
+    if lines > MAX_LINES {
+        warn!("scrollback={} is too large, keeping {} lines", lines, MAX_LINES);
+    }
+    set_scrollback_lines(lines);
+}
+
+/// Keep up to `lines` lines of scrollback for every virtual console, but
+/// no more than `MAX_LINES`. The heap must be initialized. A console
+/// whose lines cannot be allocated keeps its current scrollback.
+pub fn set_scrollback_lines(lines: usize) {
+    let lines = lines.min(MAX_LINES);
+    for index in 0..CONSOLE_COUNT {
+        // allocate and free outside of the writer's lock, so that the
+        // allocator can log to the screen
+        let mut heap = Vec::new();
+        if heap.try_reserve_exact(lines).is_err() {
+            warn!("No memory for {} lines of scrollback on console {}", lines, index);
+            continue;
+        }
+        let old = with_console(index, |writer| writer.replace_scrollback(heap, lines));
+        drop(old);
+    }
+}
+
+/// Show `lines` older lines of output on the active console.
+pub fn scroll_back(lines: usize) {
+    with_console(active_console(), |writer| writer.scroll_back(lines));
+}
+
+/// Show `lines` newer lines of output on the active console, up to the
+/// latest.
+pub fn scroll_forward(lines: usize) {
+    with_console(active_console(), |writer| writer.scroll_forward(lines));
+}
+
+
+// TESTS -----------------------------------------------------------------------
+
+#[test_case]
+fn test_scrollback() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    use super::WRITER;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let saved = writer.save();
+
+        write!(writer, "\nmarker").expect("write failed");
+        for _ in 0..BUFFER_HEIGHT {
+            writeln!(writer).expect("writeln failed");
+        }
+        writer.scroll_back(1);
+        assert!(writer.is_scrolled_back());
+        assert_eq!(writer.buffer.chars[0][0].read().ascii_character, b'm');
+
+        // new output snaps back to the live screen
+        write!(writer, "x").expect("write failed");
+        assert!(!writer.is_scrolled_back());
+        assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 1][0].read().ascii_character, b'x');
+        assert_eq!(writer.buffer.chars[0][0].read().ascii_character, b' ');
+
+        writer.restore(&saved);
+    });
+}
+
+#[test_case]
+fn test_scrollback_lines_clamped() {
+    set_scrollback_lines(usize::MAX);
+    let lines = with_console(0, |writer| writer.scrollback.lines().len());
+    assert!(lines <= MAX_LINES);
+    set_scrollback_lines(DEFAULT_LINES);
+}
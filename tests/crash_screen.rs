@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use core::ptr;
+
+use rust_os::vga_buffer::{Color, BUFFER_HEIGHT, BUFFER_WIDTH};
+use rust_os::{crash, emergency, exit_qemu, serial_print, serial_println, QemuExitCode};
+
+/// The panic message, which the crash screen shows on its fourth row.
+const MESSAGE: &str = "crash screen test";
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    emergency::enter();
+    crash::show(info);
+    if check_screen() {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("crash_screen::crash_screen...\t");
+    panic!("{}", MESSAGE);
+}
+
+/// Returns the character and color byte of a cell of the VGA text buffer.
+fn cell(row: usize, col: usize) -> (u8, u8) {
+    let cells = 0xb8000 as *const u16;
+    let cell = unsafe { ptr::read_volatile(cells.add(row * BUFFER_WIDTH + col)) };
+    (cell as u8, (cell >> 8) as u8)
+}
+
+/// Returns whether `text` is shown in `row` from `col` on.
+fn shows(row: usize, col: usize, text: &str) -> bool {
+    text.bytes().enumerate().all(|(i, byte)| cell(row, col + i).0 == byte)
+}
+
+/// Check the title bars, the background and the message of the crash
+/// screen.
+fn check_screen() -> bool {
+    let screen = (Color::Blue as u8) << 4 | Color::White as u8;
+    let bar = (Color::White as u8) << 4 | Color::Blue as u8;
+    let title = "KERNEL PANIC";
+    let footer = "System halted";
+
+    let bars = (0..BUFFER_WIDTH)
+        .all(|col| cell(0, col).1 == bar && cell(BUFFER_HEIGHT - 1, col).1 == bar);
+    let background = (1..BUFFER_HEIGHT - 1)
+        .all(|row| (0..BUFFER_WIDTH).all(|col| cell(row, col).1 >> 4 == screen >> 4));
+
+    bars && background
+        && shows(0, (BUFFER_WIDTH - title.len()) / 2, title)
+        && shows(BUFFER_HEIGHT - 1, (BUFFER_WIDTH - footer.len()) / 2, footer)
+        && cell(1, 0) == (b' ', screen)
+        && shows(3, 2, MESSAGE)
+}
@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use rust_os::{emergency, exit_qemu, println, serial_print, serial_println, QemuExitCode};
+use rust_os::vga_buffer::WRITER;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    emergency::enter();
+    // the writer is still locked by `panic_while_printing`, so this
+    // deadlocks unless the emergency path breaks the lock
+    println!("{}", info);
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    panic_while_printing();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn panic_while_printing() {
+    serial_print!("panic_while_printing::panic_while_printing...\t");
+    let _writer = WRITER.lock();
+    panic!("panicked while holding the writer");
+}